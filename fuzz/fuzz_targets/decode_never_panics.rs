@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Feeds raw, untrusted bytes into the GIL-free decode path and asserts it
+// never panics -- only ever returns `Ok` or a `CodecError`. Exercises the
+// length-prefixed decode paths (lists, binaries, bignums, tuples) directly,
+// without going through Python at all.
+fuzz_target!(|data: &[u8]| {
+    let _ = native_codec_impl::term::decode_term_from_bytes(data);
+});
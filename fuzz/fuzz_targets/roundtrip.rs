@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use native_codec_impl::term::{decode_term_from_bytes, encode_term, Term};
+
+// Generates a depth-bounded random `Term` (see `Term`'s `arbitrary`-feature
+// impl in src/term.rs), encodes it back to ETF bytes and decodes those bytes
+// again, then checks the two trees match. Generated containers hold at most
+// 4 elements and `encode_term` writes every non-empty list as `LIST_EXT`, so
+// this target never produces `LARGE_TUPLE_EXT` or `STRING_EXT`.
+fuzz_target!(|term: Term| {
+    let mut bytes = Vec::new();
+    encode_term(&term, &mut bytes).expect("a generated Term must always be encodable");
+    let decoded = decode_term_from_bytes(&bytes).expect("re-decoding our own output must succeed");
+    assert_eq!(term, decoded);
+});
@@ -12,9 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use compress::zlib;
-use cpython::*;
-use std::io::{BufReader, Read};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyList, PyString, PyTuple};
+use pyo3::PyErr;
 use std::str;
 
 use crate::reader::{Readable, Reader};
@@ -28,48 +28,90 @@ pub struct Decoder<'a> {
     py: Python<'a>, // Python instance will live at least as long as Decoder
     atom_representation: AtomRepresentation,
     bytestring_repr: ByteStringRepresentation,
-
-    pub decode_hook: PyDict,
+    bitstring_repr: helpers::BitStringRepresentation,
+    /// How to handle atoms/strings whose bytes are not valid UTF-8.
+    unicode_errors: helpers::UnicodeErrors,
+    /// When set, rejects ETF that is technically parseable but not in
+    /// minimal/canonical form (e.g. a bignum that fits in `i32`, or an atom
+    /// encoded with the wide tag when the short one would do).
+    strict: bool,
+
+    /// Current recursion depth, incremented/decremented around each call to
+    /// `decode`. Compared against `max_depth` to bound untrusted input.
+    depth: usize,
+    max_depth: Option<usize>,
+    max_container_elements: Option<usize>,
+    /// When set, caps the total bytes a `Reader` handed to this decoder will
+    /// consume (see [`crate::reader::Reader::with_max_size`]), so a declared
+    /// length prefix (list/map arity, a bignum's magnitude size, the
+    /// decompressed size of a `TAG_COMPRESSED` envelope, ...) can't force a
+    /// huge allocation before the data backing it is checked.
+    max_term_size: Option<usize>,
+
+    pub decode_hook: Py<PyDict>,
     cached_atom_pyclass: Option<PyObject>,
     cached_pid_pyclass: Option<PyObject>,
     cached_ref_pyclass: Option<PyObject>,
     cached_fun_pyclass: Option<PyObject>,
     cached_improper_list_pyclass: Option<PyObject>,
+    cached_bitstr_pyclass: Option<PyObject>,
 }
 
 impl<'a> Decoder<'a> {
     /// Create decoder instance. Parse options.
-    pub fn new(py: Python, opts: PyObject) -> CodecResult<Decoder> {
+    pub fn new(py: Python<'a>, opts: PyObject) -> CodecResult<Decoder<'a>> {
         // If opts is None, make it empty Dict, otherwise take it as PyDict
-        let opts1 = helpers::maybe_dict(py, opts);
-        let aopt = helpers::get_atom_opt(py, &opts1)?;
-        let cached_atom_pyclass = opts1.get_item(py, "atom_call");
-        let s8opt = helpers::get_byte_str_opt(py, &opts1)?;
-
-        let decode_hook = match opts1.get_item(py, "decode_hook") {
-            Some(ref h1) => PyDict::extract(py, h1)?,
-            None => PyDict::new(py),
+        let opts1 = helpers::maybe_dict(py, opts.bind(py));
+        let aopt = helpers::get_atom_opt(&opts1)?;
+        let cached_atom_pyclass = opts1.get_item("atom_call")?.map(|h| h.unbind());
+        let s8opt = helpers::get_byte_str_opt(&opts1)?;
+        let bitstr_opt = helpers::get_bitstring_opt(&opts1)?;
+        let unicode_errors = helpers::get_unicode_errors_opt(&opts1)?;
+        let strict = helpers::get_bool_opt(&opts1, "strict", false)?;
+        let max_depth = helpers::get_usize_opt(&opts1, "max_depth")?;
+        let max_container_elements = helpers::get_usize_opt(&opts1, "max_container_elements")?;
+        let max_term_size = helpers::get_usize_opt(&opts1, "max_term_size")?;
+
+        let decode_hook = match opts1.get_item("decode_hook")? {
+            Some(h1) => h1.downcast::<PyDict>().map_err(PyErr::from)?.clone().unbind(),
+            None => PyDict::new_bound(py).unbind(),
         };
 
         Ok(Decoder {
             py,
             atom_representation: aopt,
             bytestring_repr: s8opt,
+            bitstring_repr: bitstr_opt,
+            unicode_errors,
+            strict,
+            depth: 0,
+            max_depth,
+            max_container_elements,
+            max_term_size,
             decode_hook,
             cached_atom_pyclass,
             cached_pid_pyclass: None,
             cached_ref_pyclass: None,
             cached_fun_pyclass: None,
             cached_improper_list_pyclass: None,
-            //      cached_bitstr_pyclass: None,
+            cached_bitstr_pyclass: None,
         })
     }
 
+    /// Applies the `max_term_size` option (if given) to a freshly-built
+    /// `Reader` before decoding starts.
+    pub fn prepare_reader<'r>(&self, reader: Reader<'r>) -> Reader<'r> {
+        match self.max_term_size {
+            Some(n) => reader.with_max_size(n),
+            None => reader,
+        }
+    }
+
     pub fn decode_and_wrap(&mut self, reader: &mut Reader) -> Result<PyObject, CodecError> {
         let result = self.decode(reader)?;
-        let tail = PyBytes::new(self.py, reader.rest());
-        let result = PyTuple::new(self.py, &[result, tail.into_object()]);
-        Ok(result.into_object())
+        let tail = PyBytes::new_bound(self.py, reader.rest());
+        let result = PyTuple::new_bound(self.py, [result, tail.into_any().unbind()]);
+        Ok(result.into_any().unbind())
     }
 
     /// Strip 131 byte header and uncompress if the data was compressed.
@@ -84,15 +126,7 @@ impl<'a> Decoder<'a> {
         let tag = reader.peek()?;
         if tag == consts::TAG_COMPRESSED {
             reader.read_u8().unwrap();
-            let decomp_size = reader.read_u32()? as usize;
-
-            let mut decompressed = Vec::<u8>::with_capacity(decomp_size);
-            let mut d = zlib::Decoder::new(reader);
-            d.read_to_end(&mut decompressed).unwrap();
-            if decompressed.len() != decomp_size as usize {
-                return Err(CodecError::CompressedSizeMismatch);
-            }
-
+            let decompressed = reader.read_compressed()?;
             let mut decompressed_reader = (&decompressed).into();
             self.decode_and_wrap(&mut decompressed_reader)
         } else {
@@ -104,6 +138,32 @@ impl<'a> Decoder<'a> {
     /// Decodes binary External Term Format (ETF) into a Python structure.
     /// Returns: (Decoded object, remaining bytes) or CodecError
     pub fn decode(&mut self, reader: &mut Reader) -> CodecResult<PyObject> {
+        self.depth += 1;
+        if let Some(max_depth) = self.max_depth {
+            if self.depth > max_depth {
+                self.depth -= 1;
+                return Err(CodecError::DepthLimitExceeded);
+            }
+        }
+        let result = self.decode_inner(reader);
+        self.depth -= 1;
+        result
+    }
+
+    /// Checks a declared container arity against `max_container_elements`
+    /// and against the bytes actually left in `reader` (each element needs
+    /// at least one byte) before a caller allocates `Vec::with_capacity`.
+    fn check_arity(&self, reader: &Reader, arity: usize) -> CodecResult<()> {
+        if let Some(max) = self.max_container_elements {
+            if arity > max {
+                return Err(CodecError::TooManyElements);
+            }
+        }
+        reader.ensure_remaining(arity)?;
+        Ok(())
+    }
+
+    fn decode_inner(&mut self, reader: &mut Reader) -> CodecResult<PyObject> {
         let tag = reader.read_u8()?;
         let result = match tag {
             consts::TAG_ATOM_EXT => self.parse_latin1_atom::<u16>(reader),
@@ -113,8 +173,8 @@ impl<'a> Decoder<'a> {
             consts::TAG_BINARY_EXT => self.parse_binary(reader),
             consts::TAG_BIT_BINARY_EXT => self.parse_bitstring(reader),
             consts::TAG_NIL_EXT => {
-                let empty_list = PyList::new(self.py, &[]);
-                Ok(empty_list.into_object())
+                let empty_list = PyList::empty_bound(self.py);
+                Ok(empty_list.into_any().unbind())
             }
             consts::TAG_LIST_EXT => self.parse_list(reader),
             consts::TAG_STRING_EXT => self.parse_string(reader), // 16-bit sz bytestr
@@ -123,14 +183,23 @@ impl<'a> Decoder<'a> {
             consts::TAG_SMALL_BIG_EXT => {
                 let size = reader.read_u8()? as usize;
                 let sign = reader.read_u8()?;
+                self.check_canonical_bignum(tag, reader, size, sign)?;
                 self.parse_arbitrary_length_int(reader, size, sign)
             }
             consts::TAG_LARGE_BIG_EXT => {
                 let size = reader.read_u32()? as usize;
                 let sign = reader.read_u8()?;
+                if self.strict && size <= 255 {
+                    return Err(CodecError::NonCanonical {
+                        tag,
+                        reason: "should have used SMALL_BIG_EXT".into(),
+                    });
+                }
+                self.check_canonical_bignum(tag, reader, size, sign)?;
                 self.parse_arbitrary_length_int(reader, size, sign)
             }
             consts::TAG_NEW_FLOAT_EXT => self.parse_number::<f64>(reader),
+            consts::TAG_FLOAT_EXT => self.parse_legacy_float(reader),
             consts::TAG_MAP_EXT => self.parse_map(reader),
             consts::TAG_SMALL_TUPLE_EXT => {
                 let arity = reader.read_u8()? as usize;
@@ -138,6 +207,12 @@ impl<'a> Decoder<'a> {
             }
             consts::TAG_LARGE_TUPLE_EXT => {
                 let arity = reader.read_u32()? as usize;
+                if self.strict && arity < 256 {
+                    return Err(CodecError::NonCanonical {
+                        tag,
+                        reason: "should have used SMALL_TUPLE_EXT".into(),
+                    });
+                }
                 self.parse_tuple(reader, arity)
             }
             consts::TAG_PID_EXT => self.parse_pid(reader),
@@ -151,13 +226,10 @@ impl<'a> Decoder<'a> {
         match result {
             Ok(value) => {
                 // if type_name_ref is in decode_hook, call it
-                let type_name = value.get_type(self.py).name(self.py).into_owned();
+                let type_name = value.bind(self.py).get_type().name()?.to_cow()?.into_owned();
                 let type_name_ref: &str = type_name.as_ref();
-                match &self.decode_hook.get_item(self.py, type_name_ref) {
-                    Some(ref h1) => {
-                        let repr1 = h1.call(self.py, (value,), None)?;
-                        Ok(repr1)
-                    }
+                match self.decode_hook.bind(self.py).get_item(type_name_ref)? {
+                    Some(h1) => Ok(h1.call1((value,))?.unbind()),
                     None => Ok(value),
                 }
             }
@@ -169,15 +241,14 @@ impl<'a> Decoder<'a> {
     /// found - import and cache it locally.
     fn get_atom_pyclass(&mut self) -> PyObject {
         match &self.cached_atom_pyclass {
-            Some(ref a) => a.clone_ref(self.py),
+            Some(a) => a.clone_ref(self.py),
             None => {
-                let atom_m = self.py.import("term.atom").unwrap();
+                let atom_m = self.py.import_bound("term.atom").unwrap();
                 let atom_cls = match &self.atom_representation {
-                    AtomRepresentation::TermStrictAtom => {
-                        atom_m.get(self.py, "StrictAtom").unwrap()
-                    }
-                    _ => atom_m.get(self.py, "Atom").unwrap(),
-                };
+                    AtomRepresentation::TermStrictAtom => atom_m.getattr("StrictAtom").unwrap(),
+                    _ => atom_m.getattr("Atom").unwrap(),
+                }
+                .unbind();
 
                 self.cached_atom_pyclass = Some(atom_cls.clone_ref(self.py));
                 atom_cls
@@ -185,28 +256,28 @@ impl<'a> Decoder<'a> {
         }
     }
 
-    //  /// Return cached value of BitString class used for decoding. Otherwise if not
-    //  /// found - import and cache it locally.
-    //  fn get_bitstr_pyclass(&mut self) -> PyObject {
-    //    match &self.cached_bitstr_pyclass {
-    //      Some(ref a) => a.clone_ref(self.py),
-    //      None => {
-    //        let bitstr_m = self.py.import("term.bitstring").unwrap();
-    //        let bitstr_cls = bitstr_m.get(self.py, "BitString").unwrap();
-    //        self.cached_bitstr_pyclass = Some(bitstr_cls.clone_ref(self.py));
-    //        bitstr_cls
-    //      },
-    //    }
-    //  }
+    /// Return cached value of BitString class used for decoding. Otherwise if not
+    /// found - import and cache it locally.
+    fn get_bitstr_pyclass(&mut self) -> PyObject {
+        match &self.cached_bitstr_pyclass {
+            Some(a) => a.clone_ref(self.py),
+            None => {
+                let bitstr_m = self.py.import_bound("term.bitstring").unwrap();
+                let bitstr_cls = bitstr_m.getattr("BitString").unwrap().unbind();
+                self.cached_bitstr_pyclass = Some(bitstr_cls.clone_ref(self.py));
+                bitstr_cls
+            }
+        }
+    }
 
     /// Return cached value of Pid class used for decoding. Otherwise if not
     /// found - import and cache it locally.
     fn get_pid_pyclass(&mut self) -> PyObject {
         match &self.cached_pid_pyclass {
-            Some(ref a) => a.clone_ref(self.py),
+            Some(a) => a.clone_ref(self.py),
             None => {
-                let pid_m = self.py.import("term.pid").unwrap();
-                let pid_cls = pid_m.get(self.py, "Pid").unwrap();
+                let pid_m = self.py.import_bound("term.pid").unwrap();
+                let pid_cls = pid_m.getattr("Pid").unwrap().unbind();
                 self.cached_pid_pyclass = Some(pid_cls.clone_ref(self.py));
                 pid_cls
             }
@@ -217,10 +288,10 @@ impl<'a> Decoder<'a> {
     /// found - import and cache it locally.
     fn get_ref_pyclass(&mut self) -> PyObject {
         match &self.cached_ref_pyclass {
-            Some(ref a) => a.clone_ref(self.py),
+            Some(a) => a.clone_ref(self.py),
             None => {
-                let ref_m = self.py.import("term.reference").unwrap();
-                let ref_cls = ref_m.get(self.py, "Reference").unwrap();
+                let ref_m = self.py.import_bound("term.reference").unwrap();
+                let ref_cls = ref_m.getattr("Reference").unwrap().unbind();
                 self.cached_ref_pyclass = Some(ref_cls.clone_ref(self.py));
                 ref_cls
             }
@@ -231,10 +302,10 @@ impl<'a> Decoder<'a> {
     /// found - import and cache it locally.
     fn get_fun_pyclass(&mut self) -> PyObject {
         match &self.cached_fun_pyclass {
-            Some(ref a) => a.clone_ref(self.py),
+            Some(a) => a.clone_ref(self.py),
             None => {
-                let fun_m = self.py.import("term.fun").unwrap();
-                let fun_cls = fun_m.get(self.py, "Fun").unwrap();
+                let fun_m = self.py.import_bound("term.fun").unwrap();
+                let fun_cls = fun_m.getattr("Fun").unwrap().unbind();
                 self.cached_fun_pyclass = Some(fun_cls.clone_ref(self.py));
                 fun_cls
             }
@@ -243,10 +314,10 @@ impl<'a> Decoder<'a> {
 
     fn get_improper_list_pyclass(&mut self) -> PyObject {
         match &self.cached_improper_list_pyclass {
-            Some(ref l) => l.clone_ref(self.py),
+            Some(l) => l.clone_ref(self.py),
             None => {
-                let list_m = self.py.import("term.list").unwrap();
-                let improper_list_cls = list_m.get(self.py, "ImproperList").unwrap();
+                let list_m = self.py.import_bound("term.list").unwrap();
+                let improper_list_cls = list_m.getattr("ImproperList").unwrap().unbind();
                 self.cached_improper_list_pyclass = Some(improper_list_cls.clone_ref(self.py));
                 improper_list_cls
             }
@@ -256,11 +327,24 @@ impl<'a> Decoder<'a> {
     #[inline]
     fn parse_number<'inp, T>(&self, in_bytes: &mut Reader<'inp>) -> CodecResult<PyObject>
     where
-        T: ToPyObject + Readable,
+        T: IntoPy<PyObject> + Readable,
     {
         let val = in_bytes.read_with::<T>()?;
-        let py_val = val.to_py_object(self.py);
-        Ok(py_val.into_object())
+        Ok(val.into_py(self.py))
+    }
+
+    /// Parses the legacy `FLOAT_EXT` (tag 99) body: a fixed 31-byte,
+    /// NUL-padded ASCII decimal string (as written by C's `%.20e`), emitted
+    /// by Erlang nodes older than `NEW_FLOAT_EXT`. Decode-only -- this
+    /// crate's encoder always writes `NEW_FLOAT_EXT`.
+    #[inline]
+    fn parse_legacy_float(&self, reader: &mut Reader) -> CodecResult<PyObject> {
+        let txt = str::from_utf8(reader.read(31)?)?;
+        let txt = txt.trim_end_matches('\0').trim();
+        let val: f64 = txt
+            .parse()
+            .map_err(|_| CodecError::InvalidLegacyFloat)?;
+        Ok(val.into_py(self.py))
     }
 
     //  #[inline]
@@ -279,11 +363,16 @@ impl<'a> Decoder<'a> {
         usize: std::convert::From<T>,
         T: Readable,
     {
-        let sz = reader.read_with::<T>()?.into();
-        let txt = str::from_utf8(reader.read(sz)?)?;
+        let sz: usize = reader.read_with::<T>()?.into();
+        if self.strict && std::mem::size_of::<T>() > 1 && sz < 256 {
+            return Err(CodecError::NonCanonical {
+                tag: consts::TAG_ATOM_UTF8_EXT,
+                reason: "should have used SMALL_ATOM_UTF8_EXT".into(),
+            });
+        }
+        let txt = helpers::decode_utf8_with_policy(reader.read(sz)?, self.unicode_errors)?;
 
-        let result = self.create_atom(txt)?.into_object();
-        Ok(result)
+        self.create_atom(&txt)
     }
 
     #[inline]
@@ -294,54 +383,99 @@ impl<'a> Decoder<'a> {
     {
         let sz = reader.read_with::<T>()?.into();
         let buf = reader.read(sz)?;
-        let result = if buf.is_ascii() {
+        if buf.is_ascii() {
             let txt = unsafe { str::from_utf8_unchecked(buf) };
-            self.create_atom(txt)?.into_object()
+            self.create_atom(txt)
         } else {
             let txt = buf
                 .iter()
                 .map(|c| char::from_u32(*c as u32).unwrap())
                 .collect::<String>();
-            self.create_atom(&txt)?.into_object()
-        };
-
-        Ok(result)
+            self.create_atom(&txt)
+        }
     }
 
     // TODO: Make 3 functions and store fun pointer
     #[inline]
     fn create_atom(&mut self, txt: &str) -> CodecResult<PyObject> {
+        if self.strict && txt.chars().count() > 255 {
+            return Err(CodecError::NonCanonical {
+                tag: consts::TAG_ATOM_UTF8_EXT,
+                reason: "atom exceeds the 255 character system limit".into(),
+            });
+        }
+
         match txt {
-            "true" => {
-                let t = PyBool::get(self.py, true);
-                return Ok(t.into_object());
-            }
-            "false" => {
-                let t = PyBool::get(self.py, false);
-                return Ok(t.into_object());
-            }
+            "true" => return Ok(PyBool::new_bound(self.py, true).to_owned().into_any().unbind()),
+            "false" => return Ok(PyBool::new_bound(self.py, false).to_owned().into_any().unbind()),
             "undefined" => return Ok(self.py.None()),
             _ => {}
         }
 
         match self.atom_representation {
             AtomRepresentation::Bytes => {
-                let py_bytes = PyBytes::new(self.py, txt.as_ref());
-                Ok(py_bytes.into_object())
+                let py_bytes = PyBytes::new_bound(self.py, txt.as_ref());
+                Ok(py_bytes.into_any().unbind())
             }
             AtomRepresentation::Str => {
                 // Return as a string
-                let py_txt = PyString::new(self.py, txt);
-                Ok(py_txt.into_object())
+                let py_txt = PyString::new_bound(self.py, txt);
+                Ok(py_txt.into_any().unbind())
             }
             _ => {
                 // Construct Atom object (Note: performance cost)
                 let atom_obj = self.get_atom_pyclass();
-                Ok(atom_obj.call(self.py, (txt,), None)?)
+                Ok(atom_obj.call_bound(self.py, (txt,), None)?)
             }
         } // match
     }
 
+    /// In strict mode, rejects a bignum that is not in minimal form: a
+    /// leading (most-significant) zero magnitude byte, a `-0` sign byte, or
+    /// a magnitude that actually fits `TAG_INT`/`TAG_SMALL_UINT` and so
+    /// should not have been encoded as a bignum at all. Does not consume
+    /// any bytes -- `parse_arbitrary_length_int` re-reads them afterwards.
+    fn check_canonical_bignum(
+        &self,
+        tag: u8,
+        reader: &Reader,
+        size: usize,
+        sign: u8,
+    ) -> CodecResult<()> {
+        if !self.strict || size == 0 {
+            return Ok(());
+        }
+
+        let magnitude = reader.peek_n(size)?;
+        if magnitude[size - 1] == 0 {
+            return Err(CodecError::NonCanonical {
+                tag,
+                reason: "bignum magnitude has a leading zero byte".into(),
+            });
+        }
+        if sign == 1 && magnitude.iter().all(|b| *b == 0) {
+            return Err(CodecError::NonCanonical {
+                tag,
+                reason: "bignum is a negative zero".into(),
+            });
+        }
+        if size <= 4 {
+            let mut value: u64 = 0;
+            for (i, byte) in magnitude.iter().enumerate() {
+                value += (*byte as u64) << (8 * i);
+            }
+            let fits_small_int = sign == 0 && value <= u8::MAX as u64;
+            let fits_int = value <= i32::MAX as u64 + if sign == 1 { 1 } else { 0 };
+            if fits_small_int || fits_int {
+                return Err(CodecError::NonCanonical {
+                    tag,
+                    reason: "integer fits in SMALL_UINT/INT, should not use a bignum tag".into(),
+                });
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     fn parse_arbitrary_length_int(
         &self,
@@ -350,24 +484,24 @@ impl<'a> Decoder<'a> {
         sign: u8,
     ) -> CodecResult<PyObject> {
         let bin = reader.read(size)?;
-        let data = PyBytes::new(self.py, bin);
-        let builtins = self.py.import("builtins")?;
-        let py_int = builtins.get(self.py, "int")?;
-        let val = py_int.call_method(self.py, "from_bytes", (data, "little"), None)?;
+        let data = PyBytes::new_bound(self.py, bin);
+        let builtins = self.py.import_bound("builtins")?;
+        let py_int = builtins.getattr("int")?;
+        let val = py_int.call_method1("from_bytes", (data, "little"))?;
         let val = if sign == 0 {
             val
         } else {
-            val.call_method(self.py, "__mul__", (-1,), None)?
+            val.call_method1("__mul__", (-1,))?
         };
-        Ok(val.into_object())
+        Ok(val.unbind())
     }
     /// Given input _after_ binary tag, parse remaining bytes
     #[inline]
     fn parse_binary(&self, in_bytes: &mut Reader) -> CodecResult<PyObject> {
         let sz = in_bytes.read_u32()? as usize;
         let bin = in_bytes.read(sz)?;
-        let py_bytes = PyBytes::new(self.py, bin);
-        Ok(py_bytes.into_object())
+        let py_bytes = PyBytes::new_bound(self.py, bin);
+        Ok(py_bytes.into_any().unbind())
     }
 
     /// Given input _after_ bit-string tag, parse remaining bytes and bit-count
@@ -375,21 +509,30 @@ impl<'a> Decoder<'a> {
     fn parse_bitstring(&mut self, in_bytes: &mut Reader) -> CodecResult<PyObject> {
         let sz = in_bytes.read_u32()? as usize;
         let last_byte_bits: u8 = in_bytes.read_u8()?;
+        if !(1..=8).contains(&last_byte_bits) {
+            return Err(CodecError::InvalidBitStringTail { bits: last_byte_bits });
+        }
         let bin = in_bytes.read(sz)?;
-        let py_bytes = PyBytes::new(self.py, bin);
-
-        //    let py_bitstr_cls: PyObject = self.get_bitstr_pyclass();
-        //    let py_bitstr = py_bitstr_cls.call(self.py, (py_bytes, last_byte_bits), None)?;
-
-        //    Ok(py_bitstr.into_object())
-        let py_result = PyTuple::new(
-            self.py,
-            &[
-                py_bytes.into_object(),
-                last_byte_bits.to_py_object(self.py).into_object(),
-            ],
-        );
-        Ok(py_result.into_object())
+        let py_bytes = PyBytes::new_bound(self.py, bin);
+
+        match self.bitstring_repr {
+            helpers::BitStringRepresentation::Object => {
+                let py_bitstr_cls: PyObject = self.get_bitstr_pyclass();
+                let py_bitstr = py_bitstr_cls.call_bound(
+                    self.py,
+                    (py_bytes.into_any().unbind(), last_byte_bits),
+                    None,
+                )?;
+                Ok(py_bitstr)
+            }
+            helpers::BitStringRepresentation::Tuple => {
+                let py_result = PyTuple::new_bound(
+                    self.py,
+                    [py_bytes.into_any().unbind(), last_byte_bits.into_py(self.py)],
+                );
+                Ok(py_result.into_any().unbind())
+            }
+        }
     }
 
     /// Given input _after_ string tag, parse remaining bytes as an ASCII string
@@ -399,16 +542,13 @@ impl<'a> Decoder<'a> {
         let arr = reader.read(sz)?;
         let result = match self.bytestring_repr {
             ByteStringRepresentation::Str => {
-                let rust_str = str::from_utf8(arr)?;
-                PyString::new(self.py, rust_str).into_object()
+                let rust_str = helpers::decode_utf8_with_policy(arr, self.unicode_errors)?;
+                PyString::new_bound(self.py, &rust_str).into_any().unbind()
             }
-            ByteStringRepresentation::Bytes => PyBytes::new(self.py, arr).into_object(),
+            ByteStringRepresentation::Bytes => PyBytes::new_bound(self.py, arr).into_any().unbind(),
             ByteStringRepresentation::IntList => {
-                let lst: Vec<_> = arr
-                    .iter()
-                    .map(|n| n.to_py_object(self.py).into_object())
-                    .collect();
-                PyList::new(self.py, lst.as_ref()).into_object()
+                let lst: Vec<PyObject> = arr.iter().map(|n| n.into_py(self.py)).collect();
+                PyList::new_bound(self.py, lst).into_any().unbind()
             }
         };
 
@@ -419,6 +559,7 @@ impl<'a> Decoder<'a> {
     #[inline]
     fn parse_list(&mut self, reader: &mut Reader) -> CodecResult<PyObject> {
         let sz = reader.read_u32()? as usize;
+        self.check_arity(reader, sz)?;
 
         let mut lst = Vec::<PyObject>::with_capacity(sz);
 
@@ -428,43 +569,74 @@ impl<'a> Decoder<'a> {
             lst.push(val);
         }
 
-        let py_lst = PyList::new(self.py, lst.as_ref());
-
         // Check whether last element is a NIL, or something else
         if reader.peek()? == consts::TAG_NIL_EXT {
             reader.read_u8().unwrap();
             // We are looking at a proper list, so just return the result
-            Ok(py_lst.into_object())
+            self.finish_proper_list(lst)
         } else {
             // We are looking at an improper list
             let tail_val = self.decode(reader)?;
-            let improper_list_cls = self.get_improper_list_pyclass();
-            let improper_list = improper_list_cls.call(self.py, (py_lst, tail_val), None)?;
-            Ok(improper_list.into_object())
+            self.finish_improper_list(lst, tail_val)
         }
     }
 
+    /// Wraps completed list elements into a proper (NIL-terminated) Python
+    /// list. Shared by the recursive decoder and [`crate::streaming`].
+    pub(crate) fn finish_proper_list(&mut self, elements: Vec<PyObject>) -> CodecResult<PyObject> {
+        let py_lst = PyList::new_bound(self.py, elements);
+        Ok(py_lst.into_any().unbind())
+    }
+
+    /// Wraps completed list elements plus a non-NIL tail into an
+    /// `ImproperList`. Shared by the recursive decoder and
+    /// [`crate::streaming`].
+    pub(crate) fn finish_improper_list(
+        &mut self,
+        elements: Vec<PyObject>,
+        tail: PyObject,
+    ) -> CodecResult<PyObject> {
+        let py_lst = PyList::new_bound(self.py, elements);
+        let improper_list_cls = self.get_improper_list_pyclass();
+        let improper_list =
+            improper_list_cls.call_bound(self.py, (py_lst.into_any().unbind(), tail), None)?;
+        Ok(improper_list)
+    }
+
     /// Given input _after_ the TAG_MAP_EXT byte, parse map key/value pairs.
     #[inline]
     fn parse_map(&mut self, reader: &mut Reader) -> CodecResult<PyObject> {
         let arity = reader.read_u32()? as usize;
+        // Each entry is a key and a value, so needs at least 2 bytes.
+        self.check_arity(reader, arity * 2)?;
 
-        let result = PyDict::new(self.py);
+        let mut pairs = Vec::with_capacity(arity);
 
         // Read key/value pairs two at a time
         for _i in 0..arity {
             let py_key = self.decode(reader)?;
             let py_val = self.decode(reader)?;
-            result.set_item(self.py, py_key, py_val).unwrap();
+            pairs.push((py_key, py_val));
         }
 
-        Ok(result.into_object())
+        self.finish_map(pairs)
+    }
+
+    /// Wraps completed key/value pairs into a Python dict. Shared by the
+    /// recursive decoder and [`crate::streaming`].
+    pub(crate) fn finish_map(&mut self, pairs: Vec<(PyObject, PyObject)>) -> CodecResult<PyObject> {
+        let result = PyDict::new_bound(self.py);
+        for (py_key, py_val) in pairs {
+            result.set_item(py_key, py_val).unwrap();
+        }
+        Ok(result.into_any().unbind())
     }
 
     /// Given input _after_ the TAG_SMALL_TUPLE_EXT or the TAG_TUPLE_EXT byte,
     /// tuple elements into a vector and create Python tuple.
     #[inline]
     fn parse_tuple(&mut self, reader: &mut Reader, arity: usize) -> CodecResult<PyObject> {
+        self.check_arity(reader, arity)?;
         let mut result = Vec::<PyObject>::with_capacity(arity);
 
         // Read values one by one
@@ -473,8 +645,14 @@ impl<'a> Decoder<'a> {
             result.push(py_val);
         }
 
-        let py_result = PyTuple::new(self.py, result.as_ref());
-        Ok(py_result.into_object())
+        self.finish_tuple(result)
+    }
+
+    /// Wraps completed tuple elements into a Python tuple. Shared by the
+    /// recursive decoder and [`crate::streaming`].
+    pub(crate) fn finish_tuple(&mut self, elements: Vec<PyObject>) -> CodecResult<PyObject> {
+        let py_result = PyTuple::new_bound(self.py, elements);
+        Ok(py_result.into_any().unbind())
     }
 
     /// Given input _after_ the PID tag byte, parse an external pid
@@ -491,8 +669,7 @@ impl<'a> Decoder<'a> {
         let creation: u8 = reader.read_u8()?;
 
         let pid_obj = self.get_pid_pyclass();
-        let py_pid = pid_obj.call(self.py, (node, id, serial, creation), None)?;
-        Ok(py_pid.into_object())
+        Ok(pid_obj.call_bound(self.py, (node, id, serial, creation), None)?)
     }
 
     /// Given input _after_ the NEW_PID tag byte, parse an external pid
@@ -509,8 +686,7 @@ impl<'a> Decoder<'a> {
         let creation: u32 = reader.read_u32()?;
 
         let pid_obj = self.get_pid_pyclass();
-        let py_pid = pid_obj.call(self.py, (node, id, serial, creation), None)?;
-        Ok(py_pid.into_object())
+        Ok(pid_obj.call_bound(self.py, (node, id, serial, creation), None)?)
     }
 
     /// Given input _after_ the Reference tag byte, parse an external reference
@@ -527,11 +703,10 @@ impl<'a> Decoder<'a> {
         let creation: u8 = reader.read_u8()?;
 
         let id: &[u8] = reader.read(term_len * 4)?;
-        let bytes_id = PyBytes::new(self.py, id);
+        let bytes_id = PyBytes::new_bound(self.py, id);
 
         let ref_obj = self.get_ref_pyclass();
-        let py_ref = ref_obj.call(self.py, (node, creation, bytes_id), None)?;
-        Ok(py_ref.into_object())
+        Ok(ref_obj.call_bound(self.py, (node, creation, bytes_id), None)?)
     }
 
     /// Given input _after_ the Newer Reference tag byte, parse an external reference
@@ -548,11 +723,10 @@ impl<'a> Decoder<'a> {
         let creation: u32 = reader.read_u32()?;
 
         let id: &[u8] = reader.read(term_len * 4)?;
-        let bytes_id = PyBytes::new(self.py, id);
+        let bytes_id = PyBytes::new_bound(self.py, id);
 
         let ref_obj = self.get_ref_pyclass();
-        let py_ref = ref_obj.call(self.py, (node, creation, bytes_id), None)?;
-        Ok(py_ref.into_object())
+        Ok(ref_obj.call_bound(self.py, (node, creation, bytes_id), None)?)
     }
 
     /// Given input _after_ the Fun tag byte, parse a fun (not useful in Python
@@ -573,15 +747,16 @@ impl<'a> Decoder<'a> {
         let pid = self.decode(reader)?;
 
         // Decode num_free free variables following after pid
+        self.check_arity(reader, num_free as usize)?;
         let mut frozen_vars = Vec::<PyObject>::with_capacity(arity);
         for _i in 0..num_free {
             let py_val = self.decode(reader)?;
             frozen_vars.push(py_val);
         }
-        let py_frozen_vars = PyTuple::new(self.py, frozen_vars.as_ref());
+        let py_frozen_vars = PyTuple::new_bound(self.py, &frozen_vars);
 
         let fun_obj = self.get_fun_pyclass();
-        let py_fun = fun_obj.call(
+        Ok(fun_obj.call_bound(
             self.py,
             (
                 module,
@@ -591,11 +766,10 @@ impl<'a> Decoder<'a> {
                 uniq_md5,
                 old_index,
                 old_uniq,
-                py_frozen_vars.into_object(),
+                py_frozen_vars.into_any().unbind(),
             ),
             None,
-        )?;
-        Ok(py_fun.into_object())
+        )?)
     }
 }
 // end impl
@@ -16,7 +16,11 @@ use crate::helpers::VecWriteExt;
 
 use super::consts;
 use super::errors::*;
-use cpython::*;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList, PyString, PyTuple};
+use pyo3::PyErr;
 use std::borrow::Cow;
 use std::io::Write;
 use std::{i32, u16, u8};
@@ -24,24 +28,41 @@ use std::{i32, u16, u8};
 pub struct Encoder<'a> {
     pub py: Python<'a>, // Python instance will live at least as long as Encoder
     pub data: Vec<u8>,
-    pub encode_hook: PyDict,
-    pub catch_all: Option<PyObject>,
+    pub encode_hook: Bound<'a, PyDict>,
+    pub catch_all: Option<Bound<'a, PyAny>>,
     // A function py_codec_impl.generic_serialize_object used for unknown classes
-    pub cached_generic_serialize_fn: Option<PyObject>,
+    pub cached_generic_serialize_fn: Option<Bound<'a, PyAny>>,
+    /// `compressed` option: `Some(level)` zlib-deflates the term into the
+    /// `131, 80, <size>, <deflated>` envelope in [`Encoder::finish`].
+    compressed: Option<u32>,
+
+    /// Current recursion depth, incremented/decremented around each call to
+    /// [`Encoder::encode`]. Compared against `max_depth` to bound a
+    /// self-referential or pathologically nested input.
+    depth: usize,
+    max_depth: Option<usize>,
+    /// Checked against `self.data.len()` at the start of each `encode` call,
+    /// bounding total output size for untrusted input.
+    max_output_bytes: Option<usize>,
+
+    /// `deterministic` option: when set, `write_dict` sorts its key/value
+    /// pairs into Erlang standard term order before emitting `MAP_EXT`, so
+    /// equal terms always produce byte-identical output.
+    deterministic: bool,
 }
 
 impl<'a> Encoder<'a> {
-    pub fn new(py: Python, opt: PyObject) -> CodecResult<Encoder> {
-        let py_opts = if opt == py.None() {
-            PyDict::new(py)
-        } else {
-            PyDict::extract(py, &opt)?
-        };
-        let encode_hook = match py_opts.get_item(py, "encode_hook") {
-            Some(ref h1) => PyDict::extract(py, h1)?,
-            None => PyDict::new(py),
+    pub fn new(py: Python<'a>, opt: Bound<'a, PyAny>) -> CodecResult<Encoder<'a>> {
+        let py_opts = crate::helpers::maybe_dict(py, &opt);
+        let encode_hook = match py_opts.get_item("encode_hook")? {
+            Some(h) => h.downcast::<PyDict>().map_err(PyErr::from)?.clone(),
+            None => PyDict::new_bound(py),
         };
-        let catch_all = encode_hook.get_item(py, "catch_all");
+        let catch_all = encode_hook.get_item("catch_all")?;
+        let compressed = crate::helpers::get_compressed_opt(&py_opts)?;
+        let max_depth = crate::helpers::get_usize_opt(&py_opts, "max_depth")?;
+        let max_output_bytes = crate::helpers::get_usize_opt(&py_opts, "max_output_bytes")?;
+        let deterministic = crate::helpers::get_bool_opt(&py_opts, "deterministic", false)?;
 
         Ok(Encoder {
             py,
@@ -49,56 +70,108 @@ impl<'a> Encoder<'a> {
             encode_hook,
             catch_all,
             cached_generic_serialize_fn: None,
+            compressed,
+            depth: 0,
+            max_depth,
+            max_output_bytes,
+            deterministic,
         })
     }
 
-    pub fn encode(&mut self, py_term: &PyObject) -> CodecResult<()> {
-        let type_name = py_term.get_type(self.py).name(self.py).into_owned();
-        let type_name_ref: &str = type_name.as_ref();
-        match &self.encode_hook.get_item(self.py, type_name_ref) {
-            Some(ref h1) => {
-                let repr1 = h1.call(self.py, (py_term,), None)?;
+    /// Wraps the already-encoded term body (`self.data`) with the ETF
+    /// version byte, producing the bytes `term_to_binary` returns to Python.
+    ///
+    /// If the `compressed` option was given, the body is zlib-deflated into
+    /// the `131, 80, <uncompressed size>, <deflated bytes>` envelope
+    /// (matching Erlang's `term_to_binary(Term, [compressed])`), but only
+    /// when that is actually smaller than the plain encoding -- otherwise
+    /// the plain, uncompressed bytes are used. Symmetric with the `131, 80`
+    /// handling `Decoder::decode_with_131tag` already does on the way in.
+    pub fn finish(&self) -> CodecResult<Vec<u8>> {
+        if let Some(level) = self.compressed {
+            let mut deflater = ZlibEncoder::new(Vec::new(), Compression::new(level));
+            deflater.write_all(&self.data)?;
+            let deflated = deflater.finish()?;
+            if deflated.len() + 5 < self.data.len() {
+                let mut out = Vec::with_capacity(deflated.len() + 6);
+                out.push(consts::ETF_VERSION_TAG);
+                out.push(consts::TAG_COMPRESSED);
+                out.push_u32(self.data.len() as u32);
+                out.write_all(&deflated)?;
+                return Ok(out);
+            }
+        }
+
+        let mut out = Vec::with_capacity(self.data.len() + 1);
+        out.push(consts::ETF_VERSION_TAG);
+        out.write_all(&self.data)?;
+        Ok(out)
+    }
+
+    pub fn encode(&mut self, py_term: &Bound<'a, PyAny>) -> CodecResult<()> {
+        self.depth += 1;
+        if let Some(max_depth) = self.max_depth {
+            if self.depth > max_depth {
+                self.depth -= 1;
+                return Err(CodecError::DepthLimitExceeded);
+            }
+        }
+        let result = self.encode_checked(py_term);
+        self.depth -= 1;
+        result
+    }
+
+    fn encode_checked(&mut self, py_term: &Bound<'a, PyAny>) -> CodecResult<()> {
+        if let Some(max_output_bytes) = self.max_output_bytes {
+            if self.data.len() > max_output_bytes {
+                return Err(CodecError::OutputTooLarge);
+            }
+        }
+
+        let type_name = type_name_of(py_term)?;
+        match self.encode_hook.get_item(type_name.as_str())? {
+            Some(h1) => {
+                let repr1 = h1.call1((py_term.clone(),))?;
                 self.encode_default(&repr1)
             }
             None => self.encode_default(py_term),
         }
     }
 
-    pub fn encode_default(&mut self, term: &PyObject) -> CodecResult<()> {
-        let type_name = term.get_type(self.py).name(self.py).into_owned();
-        let type_name_ref: &str = type_name.as_ref();
+    pub fn encode_default(&mut self, term: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let type_name = type_name_of(term)?;
 
-        match type_name_ref {
+        match type_name.as_str() {
             "int" => self.write_int(term),
             "float" => {
-                let val: f64 = FromPyObject::extract(self.py, term)?;
+                let val: f64 = term.extract()?;
                 if !val.is_finite() {
                     return Err(CodecError::NonFiniteFloat { f: val });
                 }
                 self.write_float(val)
             }
             "list" => {
-                let as_list = PyList::extract(self.py, term)?;
-                self.write_list_no_tail(&as_list)?;
+                let as_list = term.downcast::<PyList>().map_err(PyErr::from)?;
+                self.write_list_no_tail(as_list)?;
                 self.data.push(consts::TAG_NIL_EXT);
                 Ok(())
             }
             "tuple" => {
-                let as_tup = PyTuple::extract(self.py, term)?;
-                self.write_tuple(&as_tup)
+                let as_tup = term.downcast::<PyTuple>().map_err(PyErr::from)?;
+                self.write_tuple(as_tup)
             }
             "dict" => {
-                let as_dict = PyDict::extract(self.py, term)?;
-                self.write_dict(&as_dict)
+                let as_dict = term.downcast::<PyDict>().map_err(PyErr::from)?;
+                self.write_dict(as_dict)
             }
             "Atom" => self.write_atom(term),
             "StrictAtom" => self.write_atom(term),
             "str" => {
-                let as_str = PyString::extract(self.py, term)?;
-                self.write_str(&as_str)
+                let as_str = term.downcast::<PyString>().map_err(PyErr::from)?;
+                self.write_str(as_str)
             }
             "bool" => {
-                let val: bool = FromPyObject::extract(self.py, term)?;
+                let val: bool = term.extract()?;
                 self.write_atom_from_cow(if val {
                     Cow::from("true")
                 } else {
@@ -107,21 +180,21 @@ impl<'a> Encoder<'a> {
             }
             "NoneType" => self.write_atom_from_cow(Cow::from("undefined")),
             "ImproperList" => {
-                let elements0 = term.getattr(self.py, "_elements")?;
-                let elements = PyList::extract(self.py, &elements0)?;
-                let tail = term.getattr(self.py, "_tail")?;
-                self.write_list_no_tail(&elements)?;
+                let elements0 = term.getattr("_elements")?;
+                let elements = elements0.downcast::<PyList>().map_err(PyErr::from)?;
+                let tail = term.getattr("_tail")?;
+                self.write_list_no_tail(elements)?;
                 self.encode(&tail)
             }
             "Pid" => self.write_pid(term),
             "Reference" => self.write_ref(term),
             "bytes" => {
-                let py_bytes = PyBytes::extract(self.py, term)?;
-                self.write_binary(&py_bytes)
+                let py_bytes = term.downcast::<PyBytes>().map_err(PyErr::from)?;
+                self.write_binary(py_bytes)
             }
             "BitString" => self.write_bitstring(term),
-            //"Fun" => return self.write_fun(&term),
-            _other => self.write_unknown_object(type_name_ref, term),
+            "Fun" => self.write_fun(term),
+            _other => self.write_unknown_object(type_name.as_str(), term),
         }
     }
 
@@ -129,15 +202,15 @@ impl<'a> Encoder<'a> {
     /// If no catch_all was set, check whether object has ``__etf__(self)`` member.
     /// Else encode object as Tuple(b'ClassName', Dict(b'field', values)) trying
     ///   to avoid circular loops.
-    fn write_unknown_object(&mut self, _name: &str, py_term: &PyObject) -> CodecResult<()> {
-        match &self.catch_all {
-            Some(ref h1) => {
-                let repr1 = h1.call(self.py, (py_term,), None)?;
+    fn write_unknown_object(&mut self, _name: &str, py_term: &Bound<'a, PyAny>) -> CodecResult<()> {
+        match self.catch_all.clone() {
+            Some(h1) => {
+                let repr1 = h1.call1((py_term.clone(),))?;
                 self.encode(&repr1)
             }
-            None => match py_term.getattr(self.py, "__etf__") {
+            None => match py_term.getattr("__etf__") {
                 Ok(h2) => {
-                    let repr2 = h2.call(self.py, NoArgs, None)?;
+                    let repr2 = h2.call0()?;
                     self.encode(&repr2)
                 }
                 Err(_) => self.write_generic_unknown_object(py_term),
@@ -145,39 +218,38 @@ impl<'a> Encoder<'a> {
         }
     }
 
-    fn write_generic_unknown_object(&mut self, py_term: &PyObject) -> CodecResult<()> {
-        let py_fn = match &self.cached_generic_serialize_fn {
-            Some(ref a) => a.clone_ref(self.py),
+    fn write_generic_unknown_object(&mut self, py_term: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let py_fn = match self.cached_generic_serialize_fn.clone() {
+            Some(a) => a,
             None => {
-                let pyimpl_m = self.py.import("term.py_codec_impl")?;
-                let generic_fn = pyimpl_m.get(self.py, "generic_serialize_object")?;
-                self.cached_generic_serialize_fn = Some(generic_fn.clone_ref(self.py));
+                let pyimpl_m = self.py.import_bound("term.py_codec_impl")?;
+                let generic_fn = pyimpl_m.getattr("generic_serialize_object")?;
+                self.cached_generic_serialize_fn = Some(generic_fn.clone());
                 generic_fn
             }
         };
-        let result_pair = py_fn.call(self.py, (py_term, self.py.None()), None)?;
-        let py_pair: PyTuple = PyTuple::extract(self.py, &result_pair)?;
-        let result = py_pair.get_item(self.py, 0);
+        let result_pair = py_fn.call1((py_term.clone(), self.py.None()))?;
+        let py_pair = result_pair.downcast::<PyTuple>().map_err(PyErr::from)?;
+        let result = py_pair.get_item(0)?;
         self.encode(&result)
     }
 
     /// Writes list tag with elements, but no tail element (NIL or other). Ensure
     /// that the calling code is writing either a NIL or a tail term.
     #[inline]
-    fn write_list_no_tail(&mut self, list: &PyList) -> CodecResult<()> {
-        let size = list.len(self.py);
+    fn write_list_no_tail(&mut self, list: &Bound<'a, PyList>) -> CodecResult<()> {
+        let size = list.len();
         self.data.push(consts::TAG_LIST_EXT);
         self.data.push_u32(size as u32);
-        for i in 0..size {
-            let item = list.get_item(self.py, i);
+        for item in list.iter() {
             self.encode(&item)?;
         }
         Ok(())
     }
 
     #[inline]
-    fn write_tuple(&mut self, tup: &PyTuple) -> CodecResult<()> {
-        let size = tup.len(self.py);
+    fn write_tuple(&mut self, tup: &Bound<'a, PyTuple>) -> CodecResult<()> {
+        let size = tup.len();
         if size < u8::MAX as usize {
             self.data.push(consts::TAG_SMALL_TUPLE_EXT);
             self.data.push(size as u8);
@@ -186,73 +258,197 @@ impl<'a> Encoder<'a> {
             self.data.push_u32(size as u32);
         }
 
-        for i in 0..size {
-            let item = tup.get_item(self.py, i);
+        for item in tup.iter() {
             self.encode(&item)?;
         }
         Ok(())
     }
 
-    /// Writes Erlang map from Python dict.
+    /// Writes Erlang map from Python dict. With the `deterministic` option,
+    /// entries are sorted into Erlang standard term order first, so equal
+    /// dicts always produce byte-identical `MAP_EXT` output.
     #[inline]
-    fn write_dict(&mut self, py_dict: &PyDict) -> CodecResult<()> {
-        let size = py_dict.len(self.py);
+    fn write_dict(&mut self, py_dict: &Bound<'a, PyDict>) -> CodecResult<()> {
+        let size = py_dict.len();
         self.data.push(consts::TAG_MAP_EXT);
         self.data.push_u32(size as u32);
 
-        for (py_key, py_value) in py_dict.items(self.py) {
-            self.encode(&py_key)?;
-            self.encode(&py_value)?;
+        if self.deterministic {
+            let mut pairs = Vec::with_capacity(size);
+            for (py_key, py_value) in py_dict.iter() {
+                let sort_key = self.term_order_key(&py_key)?;
+                pairs.push((sort_key, py_key, py_value));
+            }
+            pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            for (_, py_key, py_value) in pairs {
+                self.encode(&py_key)?;
+                self.encode(&py_value)?;
+            }
+        } else {
+            for (py_key, py_value) in py_dict.iter() {
+                self.encode(&py_key)?;
+                self.encode(&py_value)?;
+            }
         }
         Ok(())
     }
 
+    /// Builds a comparison key approximating Erlang's standard term order
+    /// (number < atom < reference/fun/pid < tuple < map < nil < list <
+    /// bitstring) for the `deterministic` option. Numbers, atoms, tuples,
+    /// maps, nil/lists and binaries/bitstrings are compared structurally;
+    /// reference/fun/pid and any other unrecognised type fall back to their
+    /// own already-encoded bytes, which is still total and reproducible but
+    /// not a byte-for-byte match of Erlang's real ordering for those types.
+    fn term_order_key(&mut self, term: &Bound<'a, PyAny>) -> CodecResult<TermOrderKey> {
+        let type_name = type_name_of(term)?;
+        match type_name.as_str() {
+            "int" => Ok(TermOrderKey::Number(int_order_key(term)?)),
+            "float" => {
+                let v: f64 = term.extract()?;
+                if !v.is_finite() {
+                    return Err(CodecError::NonFiniteFloat { f: v });
+                }
+                Ok(TermOrderKey::Number(NumberKey::Float(v)))
+            }
+            "bool" => {
+                let v: bool = term.extract()?;
+                let atom: &[u8] = if v { b"true" } else { b"false" };
+                Ok(TermOrderKey::Atom(atom.to_vec()))
+            }
+            "NoneType" => Ok(TermOrderKey::Atom(b"undefined".to_vec())),
+            "Atom" | "StrictAtom" => {
+                let py_text = term.downcast::<PyString>().map_err(PyErr::from)?;
+                Ok(TermOrderKey::Atom(py_text.to_cow()?.as_bytes().to_vec()))
+            }
+            "tuple" => {
+                let tup = term.downcast::<PyTuple>().map_err(PyErr::from)?;
+                let mut elements = Vec::with_capacity(tup.len());
+                for item in tup.iter() {
+                    elements.push(self.term_order_key(&item)?);
+                }
+                Ok(TermOrderKey::Tuple(tup.len(), elements))
+            }
+            "dict" => {
+                let d = term.downcast::<PyDict>().map_err(PyErr::from)?;
+                let mut pairs = Vec::with_capacity(d.len());
+                for (k, v) in d.iter() {
+                    pairs.push((self.term_order_key(&k)?, self.term_order_key(&v)?));
+                }
+                pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                Ok(TermOrderKey::Map(pairs))
+            }
+            "list" => {
+                let lst = term.downcast::<PyList>().map_err(PyErr::from)?;
+                if lst.is_empty() {
+                    return Ok(TermOrderKey::Nil);
+                }
+                let mut elements = Vec::with_capacity(lst.len());
+                for item in lst.iter() {
+                    elements.push(self.term_order_key(&item)?);
+                }
+                Ok(TermOrderKey::List(elements))
+            }
+            "str" => {
+                // Plain `str` encodes as STRING_EXT -- a char list, not an
+                // atom -- so it sorts as a list of codepoints here too.
+                let py_text = term.downcast::<PyString>().map_err(PyErr::from)?;
+                let text = py_text.to_cow()?;
+                if text.is_empty() {
+                    return Ok(TermOrderKey::Nil);
+                }
+                let elements = text
+                    .chars()
+                    .map(|c| TermOrderKey::Number(NumberKey::Float(c as u32 as f64)))
+                    .collect();
+                Ok(TermOrderKey::List(elements))
+            }
+            "bytes" => {
+                let b = term.downcast::<PyBytes>().map_err(PyErr::from)?;
+                Ok(TermOrderKey::Binary(b.as_bytes().to_vec(), 8))
+            }
+            "BitString" => {
+                let py_bytes = term.getattr("value_")?;
+                let py_bytes = py_bytes.downcast::<PyBytes>().map_err(PyErr::from)?;
+                let last_byte_bits: u8 = term.getattr("last_byte_bits_")?.extract()?;
+                Ok(TermOrderKey::Binary(py_bytes.as_bytes().to_vec(), last_byte_bits))
+            }
+            "Reference" => Ok(TermOrderKey::Other(0, self.encode_to_scratch(term)?)),
+            "Fun" => Ok(TermOrderKey::Other(1, self.encode_to_scratch(term)?)),
+            "Pid" => Ok(TermOrderKey::Other(2, self.encode_to_scratch(term)?)),
+            _ => Ok(TermOrderKey::Other(3, self.encode_to_scratch(term)?)),
+        }
+    }
+
+    /// Encodes `term` (via the normal `encode` path, so `encode_hook` and
+    /// depth/size limits still apply) into a scratch buffer without
+    /// disturbing `self.data`, for use as a comparison key.
+    fn encode_to_scratch(&mut self, term: &Bound<'a, PyAny>) -> CodecResult<Vec<u8>> {
+        let saved = std::mem::take(&mut self.data);
+        let result = self.encode(term);
+        let produced = std::mem::replace(&mut self.data, saved);
+        result?;
+        Ok(produced)
+    }
+
+    // A CPython-FFI fast path that reads a PyLongObject's digits directly
+    // (skipping `bit_length`/`to_bytes`) was tried and reverted: this crate
+    // builds with `abi3-py37`, which sets `Py_LIMITED_API` crate-wide, and
+    // `pyo3-ffi` exposes no `PyLongObject` layout access under the limited
+    // API. There is no FFI surface this could be redone against while
+    // keeping the abi3 build; closing as won't-do.
     #[inline]
-    fn write_int(&mut self, val: &PyObject) -> CodecResult<()> {
-        let size: u64 = val
-            .call_method(self.py, "bit_length", NoArgs, None)?
-            .extract(self.py)?;
+    fn write_int(&mut self, val: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let size: u64 = val.call_method0("bit_length")?.extract()?;
         let size: u32 = (size / 8 + 1) as u32;
         if size <= 4 {
-            let v: i64 = FromPyObject::extract(self.py, val)?;
+            let v: i64 = val.extract()?;
             self.write_4byte_int(v)
         } else {
             self.write_arbitrary_int(val, size)
         }
     }
 
-    fn write_arbitrary_int(&mut self, val: &PyObject, size: u32) -> CodecResult<()> {
-        if size < 256 {
-            self.data.push(consts::TAG_SMALL_BIG_EXT);
-            self.data.push(size as u8);
+    fn write_arbitrary_int(&mut self, val: &Bound<'a, PyAny>, size: u32) -> CodecResult<()> {
+        let ltz: bool = val.call_method1("__lt__", (0,))?.extract()?;
+
+        let sign: u8 = if ltz { 1 } else { 0 };
+        let magnitude = if ltz {
+            // we make new object that we multiply with -1 to switch sign, so that we get a
+            // positive value to pack
+            val.call_method1("__mul__", (-1,))?
         } else {
-            self.data.push(consts::TAG_LARGE_BIG_EXT);
-            self.data.push_u32(size);
+            val.clone()
+        };
+        let b = magnitude
+            .call_method1("to_bytes", (size, "little"))?;
+        let b = b.downcast::<PyBytes>().map_err(PyErr::from)?;
+
+        // `size` comes from `bit_length` and is a safe upper bound, but is one byte
+        // too wide whenever the magnitude is an exact multiple of 8 bits (e.g. 2**31
+        // needs 32 bits but fits in 4 magnitude bytes). Trim the spurious high zero
+        // byte so SMALL_BIG_EXT/LARGE_BIG_EXT always carry a minimal, canonical
+        // magnitude -- otherwise our own strict decode mode would reject our output.
+        let mut data: &[u8] = b.as_bytes();
+        while data.len() > 1 && *data.last().unwrap() == 0 {
+            data = &data[..data.len() - 1];
         }
 
-        let ltz: bool = val
-            .call_method(self.py, "__lt__", (0,), None)?
-            .extract(self.py)?;
-        if ltz {
-            self.data.push(1_u8); // we have a negative value
-                                     // we make new object that we multiply with -1 to switch sign, so that we get a positive
-                                     // value to pack
-            let r: PyObject = val
-                .call_method(self.py, "__mul__", (-1,), None)?
-                .extract(self.py)?;
-            let b: PyBytes = r
-                .call_method(self.py, "to_bytes", (size, "little"), None)?
-                .extract(self.py)?;
-            let data: &[u8] = b.data(self.py);
-            self.data.write_all(data)?;
+        self.write_bignum_tag_and_magnitude(data, sign)
+    }
+
+    /// Writes the `SMALL_BIG_EXT`/`LARGE_BIG_EXT` tag, size and sign byte for
+    /// an already-trimmed, already-canonical magnitude.
+    fn write_bignum_tag_and_magnitude(&mut self, data: &[u8], sign: u8) -> CodecResult<()> {
+        if data.len() < 256 {
+            self.data.push(consts::TAG_SMALL_BIG_EXT);
+            self.data.push(data.len() as u8);
         } else {
-            self.data.push(0_u8);
-            let b: PyBytes = val
-                .call_method(self.py, "to_bytes", (size, "little"), None)?
-                .extract(self.py)?;
-            let data: &[u8] = b.data(self.py);
-            self.data.write_all(data)?;
+            self.data.push(consts::TAG_LARGE_BIG_EXT);
+            self.data.push_u32(data.len() as u32);
         }
+        self.data.push(sign);
+        self.data.write_all(data)?;
         Ok(())
     }
 
@@ -280,9 +476,9 @@ impl<'a> Encoder<'a> {
 
     /// Encode a UTF-8 Atom
     #[inline]
-    fn write_atom(&mut self, py_atom: &PyObject) -> CodecResult<()> {
-        let py_text: PyString = PyString::extract(self.py, py_atom)?;
-        let text = py_text.to_string(self.py)?;
+    fn write_atom(&mut self, py_atom: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let py_text = py_atom.downcast::<PyString>().map_err(PyErr::from)?;
+        let text = py_text.to_cow()?;
         self.write_atom_from_cow(text)
     }
 
@@ -306,10 +502,15 @@ impl<'a> Encoder<'a> {
         Ok(())
     }
 
-    /// Encode a UTF-8 string
+    /// Encode a UTF-8 string.
+    // A fast path reading PyUnicode's internal representation directly
+    // (skipping the `to_cow`/UTF-8 round-trip) was tried and reverted for
+    // the same reason as `write_int`: `abi3-py37` sets `Py_LIMITED_API`
+    // crate-wide, and `pyo3-ffi` has no `PyUnicode` internals access under
+    // the limited API. Closing as won't-do.
     #[inline]
-    fn write_str(&mut self, py_str: &PyString) -> CodecResult<()> {
-        let text = py_str.to_string(self.py)?;
+    fn write_str(&mut self, py_str: &Bound<'a, PyString>) -> CodecResult<()> {
+        let text = py_str.to_cow()?;
         let byte_array: &[u8] = text.as_ref().as_ref();
         let str_byte_length: usize = byte_array.len();
         let can_be_encoded_as_bytes = can_be_encoded_as_byte_string(&text);
@@ -324,7 +525,7 @@ impl<'a> Encoder<'a> {
             self.data.push(consts::TAG_LIST_EXT);
             let chars_count = text.chars().count();
             self.data.push_u32(chars_count as u32); // chars, not bytes!
-            for (_i, ch) in text.char_indices() {
+            for ch in text.chars() {
                 self.write_4byte_int(ch as i64)?
             }
             self.data.push(consts::TAG_NIL_EXT) // list terminator
@@ -335,20 +536,16 @@ impl<'a> Encoder<'a> {
 
     /// Encode a Pid
     #[inline]
-    fn write_pid(&mut self, py_pid: &PyObject) -> CodecResult<()> {
-        let node_name = PyString::extract(self.py, &py_pid.getattr(self.py, "node_name_")?)?;
-
-        let py_id = py_pid.getattr(self.py, "id_")?;
-        let id: u32 = FromPyObject::extract(self.py, &py_id)?;
-
-        let py_serial = py_pid.getattr(self.py, "serial_")?;
-        let serial: u32 = FromPyObject::extract(self.py, &py_serial)?;
+    fn write_pid(&mut self, py_pid: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let node_name = py_pid.getattr("node_name_")?;
+        let node_name = node_name.downcast::<PyString>().map_err(PyErr::from)?;
 
-        let py_creation = py_pid.getattr(self.py, "creation_")?;
-        let creation: u32 = FromPyObject::extract(self.py, &py_creation)?;
+        let id: u32 = py_pid.getattr("id_")?.extract()?;
+        let serial: u32 = py_pid.getattr("serial_")?.extract()?;
+        let creation: u32 = py_pid.getattr("creation_")?.extract()?;
 
         self.data.push(consts::TAG_NEW_PID_EXT);
-        self.write_atom_from_cow(node_name.to_string(self.py)?)?;
+        self.write_atom_from_cow(node_name.to_cow()?)?;
         self.data.push_u32(id);
         self.data.push_u32(serial);
         self.data.push_u32(creation);
@@ -358,18 +555,19 @@ impl<'a> Encoder<'a> {
 
     /// Encode a Reference
     #[inline]
-    fn write_ref(&mut self, py_ref: &PyObject) -> CodecResult<()> {
-        let node_name = PyString::extract(self.py, &py_ref.getattr(self.py, "node_name_")?)?;
+    fn write_ref(&mut self, py_ref: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let node_name = py_ref.getattr("node_name_")?;
+        let node_name = node_name.downcast::<PyString>().map_err(PyErr::from)?;
 
-        let py_id: PyBytes = PyBytes::extract(self.py, &py_ref.getattr(self.py, "id_")?)?;
-        let id = py_id.data(self.py);
+        let py_id = py_ref.getattr("id_")?;
+        let py_id = py_id.downcast::<PyBytes>().map_err(PyErr::from)?;
+        let id = py_id.as_bytes();
 
-        let py_creation = py_ref.getattr(self.py, "creation_")?;
-        let creation: u32 = FromPyObject::extract(self.py, &py_creation)?;
+        let creation: u32 = py_ref.getattr("creation_")?.extract()?;
 
         self.data.push(consts::TAG_NEWER_REF_EXT);
         self.data.push_u16((id.len() / 4) as u16);
-        self.write_atom_from_cow(node_name.to_string(self.py)?)?;
+        self.write_atom_from_cow(node_name.to_cow()?)?;
         self.data.push_u32(creation);
         self.data.write_all(id)?;
 
@@ -378,8 +576,8 @@ impl<'a> Encoder<'a> {
 
     /// Encode a binary (byte-string)
     #[inline]
-    fn write_binary(&mut self, py_bytes: &PyBytes) -> CodecResult<()> {
-        let data: &[u8] = py_bytes.data(self.py);
+    fn write_binary(&mut self, py_bytes: &Bound<'a, PyBytes>) -> CodecResult<()> {
+        let data: &[u8] = py_bytes.as_bytes();
         self.data.push(consts::TAG_BINARY_EXT);
         self.data.push_u32(data.len() as u32);
         self.data.write_all(data)?;
@@ -388,12 +586,12 @@ impl<'a> Encoder<'a> {
 
     /// Encode a Binary bit-string (last byte has less than 8 bits)
     #[inline]
-    fn write_bitstring(&mut self, py_bits: &PyObject) -> CodecResult<()> {
-        let py_bytes = PyBytes::extract(self.py, &py_bits.getattr(self.py, "value_")?)?;
-        let data: &[u8] = py_bytes.data(self.py);
+    fn write_bitstring(&mut self, py_bits: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let py_bytes = py_bits.getattr("value_")?;
+        let py_bytes = py_bytes.downcast::<PyBytes>().map_err(PyErr::from)?;
+        let data: &[u8] = py_bytes.as_bytes();
 
-        let py_lbb = py_bits.getattr(self.py, "last_byte_bits_")?;
-        let last_byte_bits: u8 = FromPyObject::extract(self.py, &py_lbb)?;
+        let last_byte_bits: u8 = py_bits.getattr("last_byte_bits_")?.extract()?;
 
         self.data.push(consts::TAG_BIT_BINARY_EXT);
         self.data.push_u32(data.len() as u32);
@@ -401,8 +599,165 @@ impl<'a> Encoder<'a> {
         self.data.write_all(data)?;
         Ok(())
     }
+
+    /// Encode a Fun (closure). Writes the fields in the same order
+    /// `decoder.rs::parse_fun` reads them, reading the same positional arguments `Fun.__init__` is
+    /// built from back off their trailing-underscore attributes -- the same
+    /// convention `write_pid`/`write_ref` use for `Pid`/`Reference`.
+    #[inline]
+    fn write_fun(&mut self, py_fun: &Bound<'a, PyAny>) -> CodecResult<()> {
+        let module = py_fun.getattr("module_")?;
+        let arity: u8 = py_fun.getattr("arity_")?.extract()?;
+        let pid = py_fun.getattr("pid_")?;
+        let index: u32 = py_fun.getattr("index_")?.extract()?;
+        let py_uniq = py_fun.getattr("uniq_md5_")?;
+        let py_uniq = py_uniq.downcast::<PyBytes>().map_err(PyErr::from)?;
+        let old_index = py_fun.getattr("old_index_")?;
+        let old_uniq = py_fun.getattr("old_uniq_")?;
+        let py_frozen_vars = py_fun.getattr("frozen_vars_")?;
+        let frozen_vars = py_frozen_vars.downcast::<PyTuple>().map_err(PyErr::from)?;
+
+        // `Size` covers the whole term including itself, which isn't known
+        // until everything after it is written, so the body is built in a
+        // scratch buffer first (the same swap `encode_to_scratch` uses).
+        let saved = std::mem::take(&mut self.data);
+        let result = self.write_fun_body(arity, py_uniq.as_bytes(), index, &module, &old_index, &old_uniq, &pid, frozen_vars);
+        let body = std::mem::replace(&mut self.data, saved);
+        result?;
+
+        self.data.push(consts::TAG_NEW_FUN_EXT);
+        self.data.push_u32((body.len() + 4) as u32);
+        self.data.write_all(&body)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    fn write_fun_body(
+        &mut self,
+        arity: u8,
+        uniq_md5: &[u8],
+        index: u32,
+        module: &Bound<'a, PyAny>,
+        old_index: &Bound<'a, PyAny>,
+        old_uniq: &Bound<'a, PyAny>,
+        pid: &Bound<'a, PyAny>,
+        frozen_vars: &Bound<'a, PyTuple>,
+    ) -> CodecResult<()> {
+        self.data.push(arity);
+        self.data.write_all(uniq_md5)?;
+        self.data.push_u32(index);
+        self.data.push_u32(frozen_vars.len() as u32);
+        self.encode(module)?;
+        self.encode(old_index)?;
+        self.encode(old_uniq)?;
+        self.encode(pid)?;
+        for item in frozen_vars.iter() {
+            self.encode(&item)?;
+        }
+        Ok(())
+    }
 } // end impl
 
+/// Comparison key used by `write_dict`'s `deterministic` option. Variant
+/// declaration order is the cross-type rank -- the derived `PartialOrd`
+/// compares the discriminant first and only then the payload -- mirroring
+/// Erlang's standard term order: number < atom < reference/fun/pid < tuple
+/// < map < nil < list < bitstring. `Other` collapses reference/fun/pid (and
+/// anything unrecognised) into one rank, sub-ordered by an embedded tag
+/// byte and then by the term's own encoded bytes; see
+/// [`Encoder::term_order_key`].
+#[derive(Clone, PartialEq, PartialOrd)]
+enum TermOrderKey {
+    Number(NumberKey),
+    Atom(Vec<u8>),
+    Other(u8, Vec<u8>),
+    Tuple(usize, Vec<TermOrderKey>),
+    Map(Vec<(TermOrderKey, TermOrderKey)>),
+    Nil,
+    List(Vec<TermOrderKey>),
+    Binary(Vec<u8>, u8),
+}
+
+/// Payload of `TermOrderKey::Number`. `Int` holds the exact sign and
+/// big-endian, already-trimmed magnitude (the sign/trimmed-magnitude form
+/// `Encoder::write_arbitrary_int` encodes, but with the bytes in big-endian
+/// rather than little-endian order), so two distinct
+/// big integers can never collide or compare out of order the way
+/// round-tripping through `f64` would. `Int`-`Int` comparisons stay exact;
+/// comparisons against a `Float` fall back to `f64`, which is no less
+/// precise than comparing two floats against each other already is.
+#[derive(Clone, PartialEq)]
+enum NumberKey {
+    Int(bool, Vec<u8>),
+    Float(f64),
+}
+
+impl NumberKey {
+    fn as_f64(&self) -> f64 {
+        match self {
+            NumberKey::Float(v) => *v,
+            NumberKey::Int(negative, magnitude) => {
+                let mut v = 0.0f64;
+                for byte in magnitude {
+                    v = v * 256.0 + *byte as f64;
+                }
+                if *negative {
+                    -v
+                } else {
+                    v
+                }
+            }
+        }
+    }
+}
+
+impl PartialOrd for NumberKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (NumberKey::Int(neg_a, mag_a), NumberKey::Int(neg_b, mag_b)) => Some(match (neg_a, neg_b) {
+                (false, true) => std::cmp::Ordering::Greater,
+                (true, false) => std::cmp::Ordering::Less,
+                (false, false) => mag_a.len().cmp(&mag_b.len()).then_with(|| mag_a.cmp(mag_b)),
+                (true, true) => mag_b.len().cmp(&mag_a.len()).then_with(|| mag_b.cmp(mag_a)),
+            }),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+}
+
+/// Builds the exact sign+magnitude comparison key for a Python `int`, using
+/// the same `bit_length`/`__lt__`/`__mul__`/`to_bytes` dunder calls
+/// `Encoder::write_arbitrary_int` uses, just with a big-endian magnitude
+/// (convenient for lexicographic comparison) instead of little-endian.
+fn int_order_key(val: &Bound<'_, PyAny>) -> CodecResult<NumberKey> {
+    let ltz: bool = val.call_method1("__lt__", (0,))?.extract()?;
+    let magnitude_val = if ltz {
+        val.call_method1("__mul__", (-1,))?
+    } else {
+        val.clone()
+    };
+    let size: u64 = magnitude_val.call_method0("bit_length")?.extract()?;
+    let size: u32 = (size / 8 + 1) as u32;
+    let b = magnitude_val.call_method1("to_bytes", (size, "big"))?;
+    let b = b.downcast::<PyBytes>().map_err(PyErr::from)?;
+
+    let mut data: &[u8] = b.as_bytes();
+    while data.len() > 1 && data[0] == 0 {
+        data = &data[1..];
+    }
+
+    Ok(NumberKey::Int(ltz, data.to_vec()))
+}
+
+/// Reads a Python object's type's `__name__` (e.g. `"int"`, `"Pid"`), used
+/// to dispatch `encode_default` the same way `match type(term).__name__`
+/// would in Python.
+fn type_name_of(term: &Bound<'_, PyAny>) -> PyResult<String> {
+    Ok(term.get_type().name()?.to_string())
+}
+
 /// Checks first 65535 characters whether they are single-byte and are not
 /// extended code points
 fn can_be_encoded_as_byte_string(s: &str) -> bool {
@@ -14,7 +14,7 @@
 
 use std::{convert::From, str::Utf8Error};
 
-use cpython::*;
+use pyo3::PyErr;
 
 use crate::reader::ReadError;
 
@@ -26,8 +26,6 @@ use thiserror::Error;
 pub enum CodecError {
     #[error("ETF version 131 is expected")]
     UnsupportedETFVersion,
-    #[error("Compressed size does not match decompressed")]
-    CompressedSizeMismatch,
     #[error("Read failed")]
     ReadError(#[from] ReadError),
     #[error("{txt}")]
@@ -46,6 +44,18 @@ pub enum CodecError {
     EncodingError(#[from] Utf8Error),
     #[error("Atom too long")]
     AtomTooLong,
+    #[error("Non-canonical encoding for tag {}: {}", tag, reason)]
+    NonCanonical { tag: u8, reason: String },
+    #[error("Term nesting exceeds the configured max_depth")]
+    DepthLimitExceeded,
+    #[error("Container arity exceeds the configured max_container_elements")]
+    TooManyElements,
+    #[error("Encoded output exceeds the configured max_output_bytes")]
+    OutputTooLarge,
+    #[error("BIT_BINARY_EXT last byte bit count {} is out of range 1..=8", bits)]
+    InvalidBitStringTail { bits: u8 },
+    #[error("FLOAT_EXT body is not a valid float string")]
+    InvalidLegacyFloat,
 }
 
 pub type CodecResult<T> = Result<T, CodecError>;
@@ -60,25 +70,13 @@ impl From<PyErr> for CodecError {
 }
 
 impl From<CodecError> for PyErr {
-    /// Somehow this works. Create a PyErr struct without traceback, containing
-    /// a PyCodecError created from Rust CodecError with string explanation.
+    /// Raises a `PyCodecError` carrying the `CodecError`'s display text.
+    /// Unlike the `cpython` crate, pyo3 builds `PyErr` lazily from the
+    /// exception type and its constructor arguments, so no GIL is needed
+    /// here -- it is only acquired once the error actually crosses back
+    /// into Python.
     fn from(err: CodecError) -> PyErr {
-        let gil = Python::acquire_gil();
-        let py = gil.python();
-        let ty = py.get_type::<PyCodecError>();
-
-        // CodecErrors are formatted using #[fail...] attribute format string
-        let err_str = format!("{}", err);
-        let py_str = PyString::new(py, &err_str);
-        let noargs = PyTuple::new(py, &[py_str.into_object()]);
-        let err_val = ty.call(py, noargs, None).unwrap();
-
-        let tyo = ty.into_object();
-        PyErr {
-            ptype: tyo,
-            pvalue: Some(err_val),
-            ptraceback: None,
-        }
+        PyErr::new::<PyCodecError, _>(err.to_string())
     }
 }
 
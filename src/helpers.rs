@@ -12,29 +12,47 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use cpython::*;
+use std::str;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
 
 use super::errors::*;
 
 /// Get dict value with string key, expect it to be string too, or return
 /// the default value.
-pub fn get_str_opt(py: Python, opts: &PyDict, optname: &str, default: &str) -> CodecResult<String> {
-    match opts.get_item(py, optname) {
-        Some(val) => {
-            let py_str: PyString = PyString::extract(py, &val)?;
-            let s = py_str.to_string_lossy(py).into_owned();
-            Ok(s)
-        }
+pub fn get_str_opt(opts: &Bound<'_, PyDict>, optname: &str, default: &str) -> CodecResult<String> {
+    match opts.get_item(optname)? {
+        Some(val) => Ok(val.extract::<String>()?),
         None => Ok(default.to_string()),
     }
 }
 
-/// Given a dict or a possibly None, return dict
-pub fn maybe_dict(py: Python, dict_or_none: PyObject) -> PyDict {
-    if dict_or_none == py.None() {
-        PyDict::new(py)
+/// Get dict value with string key, expect it to be a bool, or return the
+/// default value.
+pub fn get_bool_opt(opts: &Bound<'_, PyDict>, optname: &str, default: bool) -> CodecResult<bool> {
+    match opts.get_item(optname)? {
+        Some(val) => Ok(val.extract::<bool>()?),
+        None => Ok(default),
+    }
+}
+
+/// Get dict value with string key, expect it to be an int, or return `None`
+/// if the option was not given at all.
+pub fn get_usize_opt(opts: &Bound<'_, PyDict>, optname: &str) -> CodecResult<Option<usize>> {
+    match opts.get_item(optname)? {
+        Some(val) => Ok(Some(val.extract::<usize>()?)),
+        None => Ok(None),
+    }
+}
+
+/// Given a dict or a possibly `None`, return a dict, borrowing the GIL
+/// token `py` for the returned `Bound`'s lifetime.
+pub fn maybe_dict<'py>(py: Python<'py>, dict_or_none: &Bound<'py, PyAny>) -> Bound<'py, PyDict> {
+    if dict_or_none.is_none() {
+        PyDict::new_bound(py)
     } else {
-        PyDict::extract(py, &dict_or_none).unwrap()
+        dict_or_none.downcast::<PyDict>().unwrap().clone()
     }
 }
 
@@ -53,9 +71,53 @@ pub enum ByteStringRepresentation {
     IntList,
 }
 
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum BitStringRepresentation {
+    /// `(bytes, last_byte_bits)`, kept for backward compatibility.
+    Tuple,
+    /// `term.bitstring.BitString(bytes, last_byte_bits)`.
+    Object,
+}
+
+/// Option: "bitstring" => "tuple" (default, back-compat) | "object"
+pub fn get_bitstring_opt(opts1: &Bound<'_, PyDict>) -> CodecResult<BitStringRepresentation> {
+    let opt_s = get_str_opt(opts1, "bitstring", "tuple")?;
+    match opt_s.as_ref() {
+        "tuple" => Ok(BitStringRepresentation::Tuple),
+        "object" => Ok(BitStringRepresentation::Object),
+        other => {
+            let txt = format!(
+                "'bitstring' option is '{}' while expected: tuple, object",
+                other
+            );
+            Err(CodecError::BadOptions { txt })
+        }
+    }
+}
+
+/// Option: "compressed" => `false` (default, no envelope) | `true` (zlib
+/// level 6) | integer `0..=9` (explicit zlib level). Mirrors Erlang's
+/// `term_to_binary(Term, [compressed])` / `[{compressed, Level}]`.
+pub fn get_compressed_opt(opts: &Bound<'_, PyDict>) -> CodecResult<Option<u32>> {
+    match opts.get_item("compressed")? {
+        None => Ok(None),
+        Some(val) => {
+            if let Ok(enabled) = val.extract::<bool>() {
+                return Ok(if enabled { Some(6) } else { None });
+            }
+            let level: u32 = val.extract::<u32>()?;
+            if level > 9 {
+                let txt = format!("'compressed' level must be 0..=9, got {}", level);
+                return Err(CodecError::BadOptions { txt });
+            }
+            Ok(Some(level))
+        }
+    }
+}
+
 /// Option: "atom" => "bytes" | "str" | "Atom" | "StrictAtom" (as Atom class, default)
-pub fn get_atom_opt(py: Python, opts1: &PyDict) -> CodecResult<AtomRepresentation> {
-    let opt_s = get_str_opt(py, opts1, "atom", "Atom")?;
+pub fn get_atom_opt(opts1: &Bound<'_, PyDict>) -> CodecResult<AtomRepresentation> {
+    let opt_s = get_str_opt(opts1, "atom", "Atom")?;
     match opt_s.as_ref() {
         "bytes" => Ok(AtomRepresentation::Bytes),
         "str" => Ok(AtomRepresentation::Str),
@@ -72,8 +134,8 @@ pub fn get_atom_opt(py: Python, opts1: &PyDict) -> CodecResult<AtomRepresentatio
 }
 
 /// Option: "byte_string" => "bytes" | "str" | "int_list" (default: str)
-pub fn get_byte_str_opt(py: Python, opts1: &PyDict) -> CodecResult<ByteStringRepresentation> {
-    let opt_s: String = get_str_opt(py, opts1, "byte_string", "str")?;
+pub fn get_byte_str_opt(opts1: &Bound<'_, PyDict>) -> CodecResult<ByteStringRepresentation> {
+    let opt_s: String = get_str_opt(opts1, "byte_string", "str")?;
     match opt_s.as_ref() {
         "bytes" => Ok(ByteStringRepresentation::Bytes),
         "str" => Ok(ByteStringRepresentation::Str),
@@ -88,6 +150,63 @@ pub fn get_byte_str_opt(py: Python, opts1: &PyDict) -> CodecResult<ByteStringRep
     }
 }
 
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum UnicodeErrors {
+    Strict,
+    Replace,
+    Ignore,
+}
+
+/// Option: "unicode_errors" => "strict" | "replace" (default) | "ignore",
+/// mirroring Python's `bytes.decode(errors=...)` names. Governs what happens
+/// when a UTF-8 atom or a `STRING_EXT` payload decoded as `str` is not valid
+/// UTF-8. `BINARY_EXT` payloads are always returned as `bytes`.
+pub fn get_unicode_errors_opt(opts1: &Bound<'_, PyDict>) -> CodecResult<UnicodeErrors> {
+    let opt_s = get_str_opt(opts1, "unicode_errors", "replace")?;
+    match opt_s.as_ref() {
+        "strict" => Ok(UnicodeErrors::Strict),
+        "replace" => Ok(UnicodeErrors::Replace),
+        "ignore" => Ok(UnicodeErrors::Ignore),
+        other => {
+            let txt = format!(
+                "'unicode_errors' option is '{}' while expected: strict, replace, ignore",
+                other
+            );
+            Err(CodecError::BadOptions { txt })
+        }
+    }
+}
+
+/// Decodes `bytes` as UTF-8 according to `policy`. `Strict` propagates the
+/// same `Utf8Error` that bare `str::from_utf8` would; `Replace` substitutes
+/// U+FFFD for bad sequences (like `String::from_utf8_lossy`); `Ignore` drops
+/// them instead of substituting.
+pub fn decode_utf8_with_policy(bytes: &[u8], policy: UnicodeErrors) -> CodecResult<String> {
+    match policy {
+        UnicodeErrors::Strict => Ok(str::from_utf8(bytes)?.to_string()),
+        UnicodeErrors::Replace => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        UnicodeErrors::Ignore => {
+            let mut out = String::with_capacity(bytes.len());
+            let mut rest = bytes;
+            while !rest.is_empty() {
+                match str::from_utf8(rest) {
+                    Ok(s) => {
+                        out.push_str(s);
+                        break;
+                    }
+                    Err(e) => {
+                        let valid_up_to = e.valid_up_to();
+                        out.push_str(unsafe { str::from_utf8_unchecked(&rest[..valid_up_to]) });
+                        let skip = e.error_len().unwrap_or(rest.len() - valid_up_to).max(1);
+                        rest = &rest[valid_up_to + skip..];
+                    }
+                }
+            }
+            Ok(out)
+        }
+    }
+}
+
 pub trait VecWriteExt {
     fn push_u32(&mut self, value: u32);
     fn push_i32(&mut self, value: i32);
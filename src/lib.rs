@@ -17,81 +17,75 @@
 //#[macro_use] extern crate lazy_static;
 extern crate byte;
 extern crate byteorder;
-extern crate compress;
-extern crate cpython;
 extern crate empty;
 
-use cpython::*;
+// Built as an abi3 (stable ABI) extension for Python 3.7+, so a single wheel
+// covers 3.12/3.13 without a per-minor-version rebuild; see the `pyo3`
+// dependency's `abi3-py37` feature in Cargo.toml.
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
 
-use self::decoder::{Decoder, wrap_decode_result};
-use self::encoder::{Encoder};
-use self::errors::{pyresult_from};
+use self::decoder::Decoder;
+use self::encoder::Encoder;
+use self::errors::pyresult_from;
 
 mod consts;
 mod decoder;
 mod encoder;
 mod errors;
 mod helpers;
+mod reader;
+mod streaming;
+// Public so the `fuzz/` crate can reach the GIL-free `Term`/`decode_term_from_bytes`/
+// `encode_term` trio without widening the PyO3-facing surface of this crate.
+pub mod term;
 
-py_exception!(native_codec_impl, PyCodecError);
-
+pyo3::create_exception!(native_codec_impl, PyCodecError, pyo3::exceptions::PyException);
 
 /// Strips 131 byte header and unpacks if the data was compressed.
-fn binary_to_term(py: Python, b: PyBytes,
-                  opts: PyObject) -> PyResult<PyObject> {
-  let mut dec_state = Decoder::new(py, opts)?;
-  pyresult_from(dec_state.decode_with_131tag(b.data(py)))
+#[pyfunction]
+fn binary_to_term(py: Python, b: &Bound<PyBytes>, opts: PyObject) -> PyResult<PyObject> {
+    let mut dec_state = Decoder::new(py, opts)?;
+    let reader: crate::reader::Reader = b.as_bytes().into();
+    let mut reader = dec_state.prepare_reader(reader);
+    pyresult_from(dec_state.decode_with_131tag(&mut reader))
 }
 
-
-fn binary_to_term_2(py: Python, b: PyBytes,
-                    opts: PyObject) -> PyResult<PyObject> {
-  let mut dec_state = Decoder::new(py, opts)?;
-  let result = dec_state.decode(b.data(py));
-  pyresult_from(wrap_decode_result(py, result))
+#[pyfunction]
+fn binary_to_term_2(py: Python, b: &Bound<PyBytes>, opts: PyObject) -> PyResult<PyObject> {
+    let mut dec_state = Decoder::new(py, opts)?;
+    let reader: crate::reader::Reader = b.as_bytes().into();
+    let mut reader = dec_state.prepare_reader(reader);
+    pyresult_from(dec_state.decode_and_wrap(&mut reader))
 }
 
+#[pyfunction]
+fn term_to_binary<'py>(py: Python<'py>, py_term: Bound<'py, PyAny>, opt: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyBytes>> {
+    let mut enc_state = Encoder::new(py, opt)?;
 
-fn term_to_binary(py: Python, py_term: PyObject,
-                  opt: PyObject) -> PyResult<PyBytes> {
-  let mut enc_state = Encoder::new(py, opt)?;
-
-  // Rest of the function is identical to ``term_to_binary_2`` except that
-  // 131 byte is pushed to the output before the encoder is called
-  enc_state.data.push(consts::ETF_VERSION_TAG);
-
-  enc_state.encode(&py_term)?;
-  Ok(PyBytes::new(py, enc_state.data.as_ref()))
+    // Rest of the function is identical to ``term_to_binary_2`` except that
+    // the 131 byte (and, with the `compressed` option, the zlib envelope) is
+    // added by ``finish`` once the term body has been fully encoded
+    enc_state.encode(&py_term)?;
+    let out = pyresult_from(enc_state.finish())?;
+    Ok(PyBytes::new_bound(py,out.as_ref()))
 }
 
-
-fn term_to_binary_2(py: Python, py_term: PyObject,
-                    opt: PyObject) -> PyResult<PyBytes> {
-  let mut enc_state = Encoder::new(py, opt)?;
-  enc_state.encode(&py_term)?;
-  Ok(PyBytes::new(py, enc_state.data.as_ref()))
+#[pyfunction]
+fn term_to_binary_2<'py>(py: Python<'py>, py_term: Bound<'py, PyAny>, opt: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyBytes>> {
+    let mut enc_state = Encoder::new(py, opt)?;
+    enc_state.encode(&py_term)?;
+    Ok(PyBytes::new_bound(py,enc_state.data.as_ref()))
 }
 
-
-// add bindings to the generated python module
-// N.B: names: "librust2py" must be the name of the `.so` or `.pyd` file
-#[inline]
-fn m_init(py: Python, m: &PyModule) -> PyResult<()> {
-  m.add(py, "__doc__", "Erlang Term Format encoding and decoding.")?;
-  m.add(py, "binary_to_term",
-        py_fn!(py, binary_to_term(b: PyBytes, opt: PyObject)))?;
-  m.add(py, "binary_to_term_2",
-        py_fn!(py, binary_to_term_2(b: PyBytes, opt: PyObject)))?;
-  m.add(py, "term_to_binary",
-        py_fn!(py, term_to_binary(py_term: PyObject, opt: PyObject)))?;
-  m.add(py, "term_to_binary_2",
-        py_fn!(py, term_to_binary_2(py_term: PyObject, opt: PyObject)))?;
-  m.add(py, "PyCodecError", py.get_type::<PyCodecError>())?;
-  Ok(())
+/// Erlang Term Format encoding and decoding.
+#[pymodule]
+fn native_codec_impl(py: Python, m: &Bound<PyModule>) -> PyResult<()> {
+    m.add("__doc__", "Erlang Term Format encoding and decoding.")?;
+    m.add_function(wrap_pyfunction!(binary_to_term, m)?)?;
+    m.add_function(wrap_pyfunction!(binary_to_term_2, m)?)?;
+    m.add_function(wrap_pyfunction!(term_to_binary, m)?)?;
+    m.add_function(wrap_pyfunction!(term_to_binary_2, m)?)?;
+    m.add("PyCodecError", py.get_type_bound::<PyCodecError>())?;
+    Ok(())
 }
-py_module_initializer!(
-  native_codec_impl,
-  initnative_codec_impl,
-  PyInit_native_codec_impl,
-  |py, m| { m_init(py, m) }
-  );
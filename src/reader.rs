@@ -22,17 +22,30 @@ type ReadResult<T> = Result<T, ReadError>;
 pub struct Reader<'a> {
     data: &'a [u8],
     offset: usize,
+    /// Bytes still allowed to be consumed via `read`, if a budget was set
+    /// with `with_max_size`. Guards against a declared length prefix (list,
+    /// binary, bignum, ...) being used to force a huge allocation before the
+    /// actual data backing it is checked.
+    budget: Option<usize>,
 }
 
 impl<'a> From<&'a [u8]> for Reader<'a> {
     fn from(data: &'a [u8]) -> Self {
-        Self { data, offset: 0 }
+        Self {
+            data,
+            offset: 0,
+            budget: None,
+        }
     }
 }
 
 impl<'a> From<&'a Vec<u8>> for Reader<'a> {
     fn from(data: &'a Vec<u8>) -> Self {
-        Self { data, offset: 0 }
+        Self {
+            data,
+            offset: 0,
+            budget: None,
+        }
     }
 }
 
@@ -76,15 +89,70 @@ impl<'a> Reader<'a> {
     }
 
     pub fn read(&mut self, n: usize) -> ReadResult<&'a [u8]> {
+        if let Some(budget) = self.budget {
+            if n > budget {
+                return Err(ReadError::LimitExceeded);
+            }
+        }
+
         let old_offset = self.offset;
         self.offset += n;
         if self.offset <= self.data.len() {
+            if let Some(budget) = &mut self.budget {
+                *budget -= n;
+            }
             Ok(&self.data[old_offset..self.offset])
         } else {
             Err(ReadError::BufferTooShort)
         }
     }
 
+    /// Caps the number of bytes this reader will hand out through `read`,
+    /// checked *before* the backing slice bounds so an attacker-controlled
+    /// length prefix (e.g. a `LARGE_BIG_EXT`/`MAP_EXT` size) can't be used to
+    /// drive allocation far beyond a sane term size.
+    pub fn with_max_size(mut self, max_term_size: usize) -> Self {
+        self.budget = Some(max_term_size);
+        self
+    }
+
+    /// Fails fast if `n` bytes are not actually available, so a decoder can
+    /// validate a declared length against the real remaining data before
+    /// calling `Vec::with_capacity(n)`.
+    pub fn ensure_remaining(&self, n: usize) -> ReadResult<()> {
+        if n <= self.data.len() - self.offset {
+            Ok(())
+        } else {
+            Err(ReadError::BufferTooShort)
+        }
+    }
+
+    /// Reads a compressed-term body (tag `80`, 4-byte big-endian
+    /// uncompressed size, then a zlib stream), inflating through `flate2`
+    /// as the stream is read rather than requiring the whole compressed
+    /// body up front, and returns the decompressed bytes. The caller wraps
+    /// the result in a fresh `Reader` to continue decoding normally.
+    pub fn read_compressed(&mut self) -> ReadResult<Vec<u8>> {
+        let decomp_size = self.read_u32()? as usize;
+        if let Some(budget) = self.budget {
+            if decomp_size > budget {
+                return Err(ReadError::LimitExceeded);
+            }
+        }
+
+        let mut decoder = flate2::read::ZlibDecoder::new(self);
+        let mut decompressed = Vec::with_capacity(decomp_size.min(1 << 20));
+        decoder
+            .read_to_end(&mut decompressed)
+            .map_err(|_| ReadError::BufferTooShort)?;
+
+        if decompressed.len() != decomp_size {
+            return Err(ReadError::CompressedSizeMismatch);
+        }
+
+        Ok(decompressed)
+    }
+
     pub fn rest(&self) -> &'a [u8] {
         &self.data[self.offset..]
     }
@@ -92,12 +160,56 @@ impl<'a> Reader<'a> {
     // pub fn done(&self) -> bool {
     //     self.data.len() <= self.offset
     // }
+
+    /// Current cursor offset, usable with [`Reader::seek_to`] to save and
+    /// later restore a position.
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+
+    /// Moves the cursor to an absolute offset previously obtained from
+    /// [`Reader::position`]. Does not validate that `offset` is in bounds;
+    /// the next `read`/`peek` will fail if it isn't.
+    pub fn seek_to(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    /// Looks ahead `n` bytes without advancing the cursor.
+    pub fn peek_n(&self, n: usize) -> ReadResult<&'a [u8]> {
+        let end = self.offset + n;
+        if end <= self.data.len() {
+            Ok(&self.data[self.offset..end])
+        } else {
+            Err(ReadError::BufferTooShort)
+        }
+    }
+
+    /// Runs `f` against this reader, restoring the cursor to its current
+    /// position if `f` returns `Err`. Lets a decoder speculatively try one
+    /// interpretation of an ambiguous tag and cleanly rewind on failure
+    /// instead of manually saving and restoring `offset` by hand.
+    pub fn transaction<T, E>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, E>) -> Result<T, E> {
+        let checkpoint = self.offset;
+        match f(self) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                self.offset = checkpoint;
+                Err(e)
+            }
+        }
+    }
 }
 
 impl<'a> Read for Reader<'a> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let to_read = (self.data.len() - self.offset).min(buf.len());
-        let slice = self.read(to_read).unwrap();
+        let slice = self
+            .read(to_read)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
         buf.copy_from_slice(slice);
         Ok(to_read)
     }
@@ -146,10 +258,50 @@ impl Readable for i32 {
     }
 }
 
+/// Reinterprets a value `Readable::do_read` already parsed big-endian as the
+/// little-endian reading of the same bytes, by swapping them back. Used by
+/// `term-derive`'s `#[term(le)]` fields, which otherwise read through the
+/// same big-endian `Readable` impls as every other field.
+pub trait LittleEndian: Sized {
+    fn swap_bytes_value(self) -> Self;
+}
+
+impl LittleEndian for u8 {
+    fn swap_bytes_value(self) -> Self {
+        self
+    }
+}
+impl LittleEndian for u16 {
+    fn swap_bytes_value(self) -> Self {
+        self.swap_bytes()
+    }
+}
+impl LittleEndian for u32 {
+    fn swap_bytes_value(self) -> Self {
+        self.swap_bytes()
+    }
+}
+impl LittleEndian for i32 {
+    fn swap_bytes_value(self) -> Self {
+        self.swap_bytes()
+    }
+}
+impl LittleEndian for f64 {
+    fn swap_bytes_value(self) -> Self {
+        f64::from_bits(self.to_bits().swap_bytes())
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ReadError {
     #[error("Buffer too short")]
     BufferTooShort,
     #[error("Buffer too short to read value")]
     BufferTooShortForValue(#[from] TryFromSliceError),
+    #[error("Requested read would exceed the configured size budget")]
+    LimitExceeded,
+    #[error("Compressed size does not match decompressed")]
+    CompressedSizeMismatch,
+    #[error("Expected tag byte {expected}, got {actual}")]
+    UnexpectedTag { expected: u8, actual: u8 },
 }
@@ -0,0 +1,247 @@
+// Copyright 2022, Erlang Solutions Ltd, and S2HC Sweden AB
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A restartable variant of [`Decoder::decode`] for terms that may span
+//! several TCP packets. Instead of recursing through `parse_list`/
+//! `parse_map`/`parse_tuple`, the work is kept on an explicit stack of
+//! [`Continuation`]s so that running out of input partway through a nested
+//! container can be reported as [`DecodeState::NeedMore`] and resumed later
+//! without re-parsing already-completed subterms.
+
+use pyo3::prelude::*;
+
+use crate::consts;
+use crate::decoder::Decoder;
+use crate::errors::{CodecError, CodecResult};
+use crate::reader::{ReadError, Reader};
+
+/// One container still being collected. `remaining` is the container's total
+/// number of elements (or key/value pairs, for `Map`); it is complete once
+/// `collected` reaches that length.
+enum Continuation {
+    List {
+        remaining: usize,
+        collected: Vec<PyObject>,
+    },
+    /// All `remaining` list elements are in, but we still need to read the
+    /// tail: either a `TAG_NIL_EXT` (proper list) or one more term (improper
+    /// list).
+    ListTail {
+        collected: Vec<PyObject>,
+    },
+    Tuple {
+        remaining: usize,
+        collected: Vec<PyObject>,
+    },
+    Map {
+        remaining: usize,
+        collected: Vec<(PyObject, PyObject)>,
+        pending_key: Option<PyObject>,
+    },
+}
+
+pub enum DecodeState {
+    Done(PyObject),
+    NeedMore,
+}
+
+/// Drives `Decoder` over a `Reader` that may not yet contain the whole term.
+/// Call `feed` again with the same logical byte stream (more bytes appended
+/// past the point the previous call left the reader at) once more data has
+/// arrived.
+#[derive(Default)]
+pub struct StreamingDecoder {
+    stack: Vec<Continuation>,
+}
+
+impl StreamingDecoder {
+    pub fn new() -> Self {
+        StreamingDecoder { stack: Vec::new() }
+    }
+
+    /// Tries to make progress decoding a single top-level term. Returns
+    /// `Done` with the finished value once the whole term (and all nested
+    /// containers) has been read, or `NeedMore` if the reader ran dry -- in
+    /// which case the reader's position is rolled back to where the current
+    /// leaf started, so the next call with more bytes available resumes
+    /// cleanly instead of re-parsing finished subterms.
+    pub fn feed(&mut self, decoder: &mut Decoder, reader: &mut Reader) -> CodecResult<DecodeState> {
+        loop {
+            let checkpoint = reader.position();
+            match self.step(decoder, reader) {
+                Ok(Some(value)) => return Ok(DecodeState::Done(value)),
+                Ok(None) => continue,
+                Err(CodecError::ReadError(ReadError::BufferTooShort)) => {
+                    reader.seek_to(checkpoint);
+                    return Ok(DecodeState::NeedMore);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads whatever the current top of the stack needs next: the list
+    /// tail marker, a new leaf/container tag, and attaches the result to the
+    /// parent continuation. Returns `Ok(Some(value))` once the full
+    /// top-level term is finished.
+    fn step(&mut self, decoder: &mut Decoder, reader: &mut Reader) -> CodecResult<Option<PyObject>> {
+        if matches!(self.stack.last(), Some(Continuation::ListTail { .. })) {
+            return self.step_list_tail(decoder, reader);
+        }
+
+        let tag = reader.peek()?;
+        match tag {
+            consts::TAG_LIST_EXT => {
+                reader.read_u8()?;
+                let sz = reader.read_u32()? as usize;
+                if sz == 0 {
+                    self.stack.push(Continuation::ListTail { collected: Vec::new() });
+                } else {
+                    self.stack.push(Continuation::List {
+                        remaining: sz,
+                        collected: Vec::with_capacity(sz),
+                    });
+                }
+                Ok(None)
+            }
+            consts::TAG_SMALL_TUPLE_EXT => {
+                reader.read_u8()?;
+                let arity = reader.read_u8()? as usize;
+                self.push_tuple(decoder, arity)
+            }
+            consts::TAG_LARGE_TUPLE_EXT => {
+                reader.read_u8()?;
+                let arity = reader.read_u32()? as usize;
+                self.push_tuple(decoder, arity)
+            }
+            consts::TAG_MAP_EXT => {
+                reader.read_u8()?;
+                let arity = reader.read_u32()? as usize;
+                if arity == 0 {
+                    let value = decoder.finish_map(Vec::new())?;
+                    return self.attach(decoder, value);
+                }
+                self.stack.push(Continuation::Map {
+                    remaining: arity,
+                    collected: Vec::with_capacity(arity),
+                    pending_key: None,
+                });
+                Ok(None)
+            }
+            _ => {
+                let value = decoder.decode(reader)?;
+                self.attach(decoder, value)
+            }
+        }
+    }
+
+    fn push_tuple(&mut self, decoder: &mut Decoder, arity: usize) -> CodecResult<Option<PyObject>> {
+        if arity == 0 {
+            let value = decoder.finish_tuple(Vec::new())?;
+            return self.attach(decoder, value);
+        }
+        self.stack.push(Continuation::Tuple {
+            remaining: arity,
+            collected: Vec::with_capacity(arity),
+        });
+        Ok(None)
+    }
+
+    /// The list we are collecting has all its elements; read the tail
+    /// (`NIL_EXT` for a proper list, or one more decoded term otherwise).
+    fn step_list_tail(&mut self, decoder: &mut Decoder, reader: &mut Reader) -> CodecResult<Option<PyObject>> {
+        // The tail is read before the `ListTail` is popped, so running out of
+        // input here leaves it on the stack for the next `feed`.
+        let tail = if reader.peek()? == consts::TAG_NIL_EXT {
+            reader.read_u8()?;
+            None
+        } else {
+            // Improper list: the tail term is read in one shot (same as the
+            // non-streaming decoder); only the top container levels resume
+            // across reads.
+            Some(decoder.decode(reader)?)
+        };
+
+        let collected = match self.stack.pop() {
+            Some(Continuation::ListTail { collected }) => collected,
+            _ => unreachable!("step_list_tail called without a ListTail on top"),
+        };
+        let value = match tail {
+            None => decoder.finish_proper_list(collected)?,
+            Some(tail) => decoder.finish_improper_list(collected, tail)?,
+        };
+        self.attach(decoder, value)
+    }
+
+    /// Attaches a finished leaf (or a container that just completed) to
+    /// whatever is now on top of the stack, finishing that container in turn
+    /// if it was the last piece it was waiting for.
+    fn attach(&mut self, decoder: &mut Decoder, value: PyObject) -> CodecResult<Option<PyObject>> {
+        let mut value = value;
+        loop {
+            match self.stack.pop() {
+                None => return Ok(Some(value)),
+                Some(Continuation::ListTail { .. }) => {
+                    unreachable!(
+                        "attach called with a ListTail on top; step() routes to step_list_tail \
+                         before ever calling attach while one is on the stack"
+                    )
+                }
+                Some(Continuation::List { remaining, mut collected }) => {
+                    collected.push(value);
+                    if collected.len() < remaining {
+                        self.stack.push(Continuation::List { remaining, collected });
+                    } else {
+                        self.stack.push(Continuation::ListTail { collected });
+                    }
+                    return Ok(None);
+                }
+                Some(Continuation::Tuple { remaining, mut collected }) => {
+                    collected.push(value);
+                    if collected.len() < remaining {
+                        self.stack.push(Continuation::Tuple { remaining, collected });
+                        return Ok(None);
+                    }
+                    value = decoder.finish_tuple(collected)?;
+                }
+                Some(Continuation::Map {
+                    remaining,
+                    mut collected,
+                    pending_key,
+                }) => match pending_key {
+                    None => {
+                        self.stack.push(Continuation::Map {
+                            remaining,
+                            collected,
+                            pending_key: Some(value),
+                        });
+                        return Ok(None);
+                    }
+                    Some(key) => {
+                        collected.push((key, value));
+                        if collected.len() < remaining {
+                            self.stack.push(Continuation::Map {
+                                remaining,
+                                collected,
+                                pending_key: None,
+                            });
+                            return Ok(None);
+                        }
+                        value = decoder.finish_map(collected)?;
+                    }
+                },
+            }
+        }
+    }
+}
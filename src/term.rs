@@ -0,0 +1,551 @@
+// Copyright 2022, Erlang Solutions Ltd, and S2HC Sweden AB
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A native Rust representation of an ETF term, decoded and re-encoded
+//! without touching Python or holding the GIL. This is *not* part of the
+//! production `binary_to_term`/`term_to_binary` path (that stays on
+//! `Decoder`/`Encoder` in `decoder.rs`/`encoder.rs`, which apply the
+//! `atom`/`byte_string`/`decode_hook`/`strict`/limit options this module
+//! has no notion of) -- it exists so `decode_to_term`/`encode_term` can be
+//! unit-tested and fuzzed with plain Rust asserts, and so a `Term` tree can
+//! round-trip without a Python interpreter. See `fuzz/fuzz_targets/`.
+
+use std::str;
+
+use term_derive::Readable;
+
+use crate::consts;
+use crate::errors::{CodecError, CodecResult};
+use crate::helpers::VecWriteExt;
+use crate::reader::Reader;
+
+/// Fixed-width fields of a `NEW_PID_EXT` body, after the node atom:
+/// `(id, serial, creation)`. Read via `#[derive(Readable)]` rather than
+/// three separate `reader.read_u32()` calls, since the field order and
+/// types line up exactly with what the derive already generates.
+#[derive(Readable)]
+struct NewPidFields {
+    id: u32,
+    serial: u32,
+    creation: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Atom(String),
+    Int(i64),
+    BigInt(Vec<u8>, u8),
+    Float(f64),
+    Binary(Vec<u8>),
+    BitString(Vec<u8>, u8),
+    Tuple(Vec<Term>),
+    List(Vec<Term>, Box<Term>),
+    Map(Vec<(Term, Term)>),
+    Pid {
+        node: Box<Term>,
+        id: u32,
+        serial: u32,
+        creation: u32,
+    },
+    Reference {
+        node: Box<Term>,
+        creation: u32,
+        id: Vec<u8>,
+    },
+    Fun {
+        module: Box<Term>,
+        arity: u8,
+        pid: Box<Term>,
+        index: u32,
+        uniq: Vec<u8>,
+        old_index: Box<Term>,
+        old_uniq: Box<Term>,
+        frozen_vars: Vec<Term>,
+    },
+}
+
+/// Recursion limit for [`decode_to_term`]. `Decoder` gets this from the
+/// caller-supplied `max_depth` option; this module has no options struct to
+/// carry one, so a fixed bound is used instead -- unguarded recursion on
+/// attacker-controlled nesting is exactly what
+/// `fuzz_targets/decode_never_panics.rs` exists to catch.
+const MAX_DEPTH: usize = 512;
+
+/// Checks a declared container/frozen-vars count against the bytes actually
+/// left in `reader` (each element needs at least one byte) before a caller
+/// allocates `Vec::with_capacity`. Mirrors `Decoder::check_arity`, minus the
+/// `max_container_elements` option this module has no notion of.
+fn check_arity(reader: &Reader, arity: usize) -> CodecResult<()> {
+    reader.ensure_remaining(arity)?;
+    Ok(())
+}
+
+/// Decodes a single ETF term (the input _after_ the `131` version byte and
+/// any compression envelope) into a [`Term`] tree, doing no Python work.
+pub fn decode_to_term(reader: &mut Reader) -> CodecResult<Term> {
+    decode_to_term_at_depth(reader, 0)
+}
+
+fn decode_to_term_at_depth(reader: &mut Reader, depth: usize) -> CodecResult<Term> {
+    if depth > MAX_DEPTH {
+        return Err(CodecError::DepthLimitExceeded);
+    }
+    let tag = reader.read_u8()?;
+    match tag {
+        consts::TAG_ATOM_EXT => decode_latin1_atom(reader, |r| Ok(r.read_u16()? as usize)),
+        consts::TAG_SMALL_ATOM_EXT => decode_latin1_atom(reader, |r| Ok(r.read_u8()? as usize)),
+        consts::TAG_ATOM_UTF8_EXT => decode_utf8_atom(reader, |r| Ok(r.read_u16()? as usize)),
+        consts::TAG_SMALL_ATOM_UTF8_EXT => decode_utf8_atom(reader, |r| Ok(r.read_u8()? as usize)),
+        consts::TAG_SMALL_UINT => Ok(Term::Int(reader.read_u8()? as i64)),
+        consts::TAG_INT => Ok(Term::Int(reader.read_i32()? as i64)),
+        consts::TAG_NEW_FLOAT_EXT => Ok(Term::Float(reader.read_f64()?)),
+        consts::TAG_FLOAT_EXT => decode_legacy_float(reader),
+        consts::TAG_SMALL_BIG_EXT => {
+            let size = reader.read_u8()? as usize;
+            let sign = reader.read_u8()?;
+            Ok(bignum_term(reader.read(size)?, sign))
+        }
+        consts::TAG_LARGE_BIG_EXT => {
+            let size = reader.read_u32()? as usize;
+            let sign = reader.read_u8()?;
+            Ok(bignum_term(reader.read(size)?, sign))
+        }
+        consts::TAG_BINARY_EXT => {
+            let sz = reader.read_u32()? as usize;
+            Ok(Term::Binary(reader.read(sz)?.to_vec()))
+        }
+        consts::TAG_BIT_BINARY_EXT => {
+            let sz = reader.read_u32()? as usize;
+            let last_byte_bits = reader.read_u8()?;
+            Ok(Term::BitString(reader.read(sz)?.to_vec(), last_byte_bits))
+        }
+        consts::TAG_NIL_EXT => Ok(Term::List(Vec::new(), Box::new(Term::Atom("[]".into())))),
+        consts::TAG_STRING_EXT => {
+            let sz = reader.read_u16()? as usize;
+            let bytes = reader.read(sz)?;
+            let elements = bytes.iter().map(|b| Term::Int(*b as i64)).collect();
+            Ok(Term::List(elements, Box::new(Term::Atom("[]".into()))))
+        }
+        consts::TAG_LIST_EXT => {
+            let sz = reader.read_u32()? as usize;
+            check_arity(reader, sz)?;
+            let mut elements = Vec::with_capacity(sz);
+            for _ in 0..sz {
+                elements.push(decode_to_term_at_depth(reader, depth + 1)?);
+            }
+            let tail = if reader.peek()? == consts::TAG_NIL_EXT {
+                reader.read_u8()?;
+                Term::Atom("[]".into())
+            } else {
+                decode_to_term_at_depth(reader, depth + 1)?
+            };
+            Ok(Term::List(elements, Box::new(tail)))
+        }
+        consts::TAG_MAP_EXT => {
+            let arity = reader.read_u32()? as usize;
+            // Each entry is a key and a value, so needs at least 2 bytes.
+            check_arity(reader, arity * 2)?;
+            let mut pairs = Vec::with_capacity(arity);
+            for _ in 0..arity {
+                let k = decode_to_term_at_depth(reader, depth + 1)?;
+                let v = decode_to_term_at_depth(reader, depth + 1)?;
+                pairs.push((k, v));
+            }
+            Ok(Term::Map(pairs))
+        }
+        consts::TAG_SMALL_TUPLE_EXT => {
+            let arity = reader.read_u8()? as usize;
+            decode_tuple(reader, arity, depth)
+        }
+        consts::TAG_LARGE_TUPLE_EXT => {
+            let arity = reader.read_u32()? as usize;
+            decode_tuple(reader, arity, depth)
+        }
+        consts::TAG_PID_EXT => {
+            let node = decode_to_term_at_depth(reader, depth + 1)?;
+            let id = reader.read_u32()?;
+            let serial = reader.read_u32()?;
+            let creation = reader.read_u8()? as u32;
+            Ok(Term::Pid {
+                node: Box::new(node),
+                id,
+                serial,
+                creation,
+            })
+        }
+        consts::TAG_NEW_PID_EXT => {
+            let node = decode_to_term_at_depth(reader, depth + 1)?;
+            let NewPidFields { id, serial, creation } = reader.read_with()?;
+            Ok(Term::Pid {
+                node: Box::new(node),
+                id,
+                serial,
+                creation,
+            })
+        }
+        consts::TAG_NEW_REF_EXT => {
+            let term_len = reader.read_u16()? as usize;
+            let node = decode_to_term_at_depth(reader, depth + 1)?;
+            let creation = reader.read_u8()? as u32;
+            let id = reader.read(term_len * 4)?.to_vec();
+            Ok(Term::Reference {
+                node: Box::new(node),
+                creation,
+                id,
+            })
+        }
+        consts::TAG_NEWER_REF_EXT => {
+            let term_len = reader.read_u16()? as usize;
+            let node = decode_to_term_at_depth(reader, depth + 1)?;
+            let creation = reader.read_u32()?;
+            let id = reader.read(term_len * 4)?.to_vec();
+            Ok(Term::Reference {
+                node: Box::new(node),
+                creation,
+                id,
+            })
+        }
+        consts::TAG_NEW_FUN_EXT => {
+            let _size = reader.read_u32()?;
+            let arity = reader.read_u8()?;
+            let uniq = reader.read(16)?.to_vec();
+            let index = reader.read_u32()?;
+            let num_free = reader.read_u32()?;
+            let module = decode_to_term_at_depth(reader, depth + 1)?;
+            let old_index = decode_to_term_at_depth(reader, depth + 1)?;
+            let old_uniq = decode_to_term_at_depth(reader, depth + 1)?;
+            let pid = decode_to_term_at_depth(reader, depth + 1)?;
+            check_arity(reader, num_free as usize)?;
+            let mut frozen_vars = Vec::with_capacity(num_free as usize);
+            for _ in 0..num_free {
+                frozen_vars.push(decode_to_term_at_depth(reader, depth + 1)?);
+            }
+            Ok(Term::Fun {
+                module: Box::new(module),
+                arity,
+                pid: Box::new(pid),
+                index,
+                uniq,
+                old_index: Box::new(old_index),
+                old_uniq: Box::new(old_uniq),
+                frozen_vars,
+            })
+        }
+        b => Err(crate::errors::CodecError::UnknownTermTagByte { b }),
+    }
+}
+
+/// Builds a [`Reader`] over `data` and decodes a single term from its front.
+/// A thin convenience wrapper around [`decode_to_term`] for callers (fuzz
+/// targets, property tests) that only have a raw byte slice.
+pub fn decode_term_from_bytes(data: &[u8]) -> CodecResult<Term> {
+    let mut reader: Reader = data.into();
+    decode_to_term(&mut reader)
+}
+
+/// Re-serializes a [`Term`] tree back into ETF bytes (the input _after_ the
+/// `131` version byte), the mirror image of [`decode_to_term`]. Kept
+/// entirely Python-free, like `decode_to_term`, so the round-trip fuzz
+/// target/property test in `fuzz/fuzz_targets/roundtrip.rs` can run as
+/// plain Rust asserts instead of needing an embedded interpreter.
+pub fn encode_term(term: &Term, out: &mut Vec<u8>) -> CodecResult<()> {
+    match term {
+        Term::Atom(s) => encode_atom(s, out),
+        Term::Int(i) => {
+            encode_int(*i, out);
+            Ok(())
+        }
+        Term::BigInt(magnitude, sign) => {
+            let mut data = magnitude.clone();
+            while data.len() > 1 && *data.last().unwrap() == 0 {
+                data.pop();
+            }
+            if data.len() < 256 {
+                out.push(consts::TAG_SMALL_BIG_EXT);
+                out.push(data.len() as u8);
+            } else {
+                out.push(consts::TAG_LARGE_BIG_EXT);
+                out.push_u32(data.len() as u32);
+            }
+            out.push(*sign);
+            out.extend_from_slice(&data);
+            Ok(())
+        }
+        Term::Float(f) => {
+            out.push(consts::TAG_NEW_FLOAT_EXT);
+            out.push_f64(*f);
+            Ok(())
+        }
+        Term::Binary(bytes) => {
+            out.push(consts::TAG_BINARY_EXT);
+            out.push_u32(bytes.len() as u32);
+            out.extend_from_slice(bytes);
+            Ok(())
+        }
+        Term::BitString(bytes, last_byte_bits) => {
+            out.push(consts::TAG_BIT_BINARY_EXT);
+            out.push_u32(bytes.len() as u32);
+            out.push(*last_byte_bits);
+            out.extend_from_slice(bytes);
+            Ok(())
+        }
+        Term::Tuple(elements) => {
+            if elements.len() < 256 {
+                out.push(consts::TAG_SMALL_TUPLE_EXT);
+                out.push(elements.len() as u8);
+            } else {
+                out.push(consts::TAG_LARGE_TUPLE_EXT);
+                out.push_u32(elements.len() as u32);
+            }
+            for e in elements {
+                encode_term(e, out)?;
+            }
+            Ok(())
+        }
+        Term::List(elements, tail) => {
+            if elements.is_empty() && matches!(tail.as_ref(), Term::Atom(a) if a == "[]") {
+                out.push(consts::TAG_NIL_EXT);
+                return Ok(());
+            }
+            out.push(consts::TAG_LIST_EXT);
+            out.push_u32(elements.len() as u32);
+            for e in elements {
+                encode_term(e, out)?;
+            }
+            match tail.as_ref() {
+                Term::Atom(a) if a == "[]" => out.push(consts::TAG_NIL_EXT),
+                other => encode_term(other, out)?,
+            }
+            Ok(())
+        }
+        Term::Map(pairs) => {
+            out.push(consts::TAG_MAP_EXT);
+            out.push_u32(pairs.len() as u32);
+            for (k, v) in pairs {
+                encode_term(k, out)?;
+                encode_term(v, out)?;
+            }
+            Ok(())
+        }
+        Term::Pid {
+            node,
+            id,
+            serial,
+            creation,
+        } => {
+            out.push(consts::TAG_NEW_PID_EXT);
+            encode_term(node, out)?;
+            out.push_u32(*id);
+            out.push_u32(*serial);
+            out.push_u32(*creation);
+            Ok(())
+        }
+        Term::Reference { node, creation, id } => {
+            out.push(consts::TAG_NEWER_REF_EXT);
+            out.push_u16((id.len() / 4) as u16);
+            encode_term(node, out)?;
+            out.push_u32(*creation);
+            out.extend_from_slice(id);
+            Ok(())
+        }
+        Term::Fun {
+            module,
+            arity,
+            pid,
+            index,
+            uniq,
+            old_index,
+            old_uniq,
+            frozen_vars,
+        } => {
+            let mut body = Vec::new();
+            body.push(*arity);
+            body.extend_from_slice(uniq);
+            body.extend(index.to_be_bytes());
+            body.extend((frozen_vars.len() as u32).to_be_bytes());
+            encode_term(module, &mut body)?;
+            encode_term(old_index, &mut body)?;
+            encode_term(old_uniq, &mut body)?;
+            encode_term(pid, &mut body)?;
+            for v in frozen_vars {
+                encode_term(v, &mut body)?;
+            }
+            out.push(consts::TAG_NEW_FUN_EXT);
+            out.push_u32((body.len() + 4) as u32);
+            out.extend_from_slice(&body);
+            Ok(())
+        }
+    }
+}
+
+fn encode_atom(s: &str, out: &mut Vec<u8>) -> CodecResult<()> {
+    let bytes = s.as_bytes();
+    if bytes.len() <= u8::MAX as usize {
+        out.push(consts::TAG_SMALL_ATOM_UTF8_EXT);
+        out.push(bytes.len() as u8);
+    } else if bytes.len() <= u16::MAX as usize {
+        out.push(consts::TAG_ATOM_UTF8_EXT);
+        out.push_u16(bytes.len() as u16);
+    } else {
+        return Err(CodecError::AtomTooLong);
+    }
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn encode_int(i: i64, out: &mut Vec<u8>) {
+    if (0..=u8::MAX as i64).contains(&i) {
+        out.push(consts::TAG_SMALL_UINT);
+        out.push(i as u8);
+    } else if (i32::MIN as i64..=i32::MAX as i64).contains(&i) {
+        out.push(consts::TAG_INT);
+        out.push_i32(i as i32);
+    } else {
+        let sign: u8 = if i < 0 { 1 } else { 0 };
+        let mut data = (i as i128).unsigned_abs().to_le_bytes().to_vec();
+        while data.len() > 1 && *data.last().unwrap() == 0 {
+            data.pop();
+        }
+        out.push(consts::TAG_SMALL_BIG_EXT);
+        out.push(data.len() as u8);
+        out.push(sign);
+        out.extend_from_slice(&data);
+    }
+}
+
+/// Depth-bounded random [`Term`] generation for the round-trip fuzz target
+/// in `fuzz/fuzz_targets/roundtrip.rs`. Gated behind the `arbitrary`
+/// feature (an optional dependency on the `arbitrary` crate) so production
+/// builds never pull it in. Scoped to the kinds that fuzz target actually
+/// exercises -- atoms, integers, floats, binaries, tuples, lists and maps --
+/// leaving bignums, bit-strings, pids, references and funs to the
+/// byte-level `decode_never_panics` fuzz target instead.
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl {
+    use super::Term;
+    use arbitrary::{Arbitrary, Unstructured};
+
+    /// Container nesting is capped at this many levels so generated terms
+    /// stay finite. Each generated tuple, list or map holds at most 4
+    /// elements, so tuples always encode as `SMALL_TUPLE_EXT`.
+    const MAX_DEPTH: u8 = 4;
+
+    impl<'a> Arbitrary<'a> for Term {
+        fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+            arbitrary_term(u, MAX_DEPTH)
+        }
+    }
+
+    fn arbitrary_term(u: &mut Unstructured<'_>, fuel: u8) -> arbitrary::Result<Term> {
+        let max_variant = if fuel == 0 { 3 } else { 6 };
+        Ok(match u.int_in_range(0..=max_variant)? {
+            0 => Term::Atom(String::arbitrary(u)?),
+            1 => Term::Int(i64::arbitrary(u)?),
+            2 => {
+                // Keep generated floats finite: ETF (and this round-trip
+                // test) has no representation for NaN/infinity.
+                let f = f64::arbitrary(u)?;
+                Term::Float(if f.is_finite() { f } else { 0.0 })
+            }
+            3 => Term::Binary(Vec::<u8>::arbitrary(u)?),
+            4 => {
+                let len = u.int_in_range(0..=4)?;
+                let mut elements = Vec::with_capacity(len);
+                for _ in 0..len {
+                    elements.push(arbitrary_term(u, fuel - 1)?);
+                }
+                Term::Tuple(elements)
+            }
+            5 => {
+                let len = u.int_in_range(0..=4)?;
+                let mut elements = Vec::with_capacity(len);
+                for _ in 0..len {
+                    elements.push(arbitrary_term(u, fuel - 1)?);
+                }
+                Term::List(elements, Box::new(Term::Atom("[]".into())))
+            }
+            _ => {
+                let len = u.int_in_range(0..=4)?;
+                let mut pairs = Vec::with_capacity(len);
+                for _ in 0..len {
+                    pairs.push((arbitrary_term(u, fuel - 1)?, arbitrary_term(u, fuel - 1)?));
+                }
+                Term::Map(pairs)
+            }
+        })
+    }
+}
+
+/// Turns a bignum body into `Term::Int` when its value fits in `i64` (the
+/// mirror of `encode_int`, which writes `SMALL_BIG_EXT` for any `i64` outside
+/// the `i32` range), or into `Term::BigInt` otherwise.
+fn bignum_term(magnitude: &[u8], sign: u8) -> Term {
+    let significant = magnitude.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    if significant <= 8 {
+        let mut value: u64 = 0;
+        for (i, byte) in magnitude[..significant].iter().enumerate() {
+            value |= (*byte as u64) << (8 * i);
+        }
+        let value = if sign == 0 { value as i128 } else { -(value as i128) };
+        if let Ok(v) = i64::try_from(value) {
+            return Term::Int(v);
+        }
+    }
+    Term::BigInt(magnitude.to_vec(), sign)
+}
+
+fn decode_tuple(reader: &mut Reader, arity: usize, depth: usize) -> CodecResult<Term> {
+    check_arity(reader, arity)?;
+    let mut elements = Vec::with_capacity(arity);
+    for _ in 0..arity {
+        elements.push(decode_to_term_at_depth(reader, depth + 1)?);
+    }
+    Ok(Term::Tuple(elements))
+}
+
+fn decode_latin1_atom(
+    reader: &mut Reader,
+    read_len: impl FnOnce(&mut Reader) -> CodecResult<usize>,
+) -> CodecResult<Term> {
+    let sz = read_len(reader)?;
+    let buf = reader.read(sz)?;
+    let txt = if buf.is_ascii() {
+        unsafe { str::from_utf8_unchecked(buf) }.to_string()
+    } else {
+        buf.iter().map(|c| char::from(*c)).collect::<String>()
+    };
+    Ok(Term::Atom(txt))
+}
+
+fn decode_utf8_atom(
+    reader: &mut Reader,
+    read_len: impl FnOnce(&mut Reader) -> CodecResult<usize>,
+) -> CodecResult<Term> {
+    let sz = read_len(reader)?;
+    let txt = str::from_utf8(reader.read(sz)?)?;
+    Ok(Term::Atom(txt.to_string()))
+}
+
+/// Decodes the legacy `FLOAT_EXT` (tag 99) body: a fixed 31-byte, NUL-padded
+/// ASCII decimal string (as written by C's `%.20e`), emitted by Erlang nodes
+/// older than `NEW_FLOAT_EXT`. Decode-only -- `encode_term` always writes
+/// `NEW_FLOAT_EXT`.
+fn decode_legacy_float(reader: &mut Reader) -> CodecResult<Term> {
+    let txt = str::from_utf8(reader.read(31)?)?;
+    let txt = txt.trim_end_matches('\0').trim();
+    let val: f64 = txt
+        .parse()
+        .map_err(|_| CodecError::InvalidLegacyFloat)?;
+    Ok(Term::Float(val))
+}
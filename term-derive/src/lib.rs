@@ -0,0 +1,187 @@
+// Copyright 2022, Erlang Solutions Ltd, and S2HC Sweden AB
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `#[derive(Readable)]`: generates `Readable::do_read` for a struct by
+//! reading each field in declaration order via `reader.read_with::<FieldTy>()`.
+//! Field attributes tweak the generated read:
+//!
+//!   - `#[term(be)]` / `#[term(le)]` - endianness for integer/float fields
+//!     (default is big-endian, matching ETF). `le` re-reads the field's
+//!     normal big-endian `Readable::do_read` result and byte-swaps it back
+//!     via `crate::reader::LittleEndian`. Generated code resolves `crate::`
+//!     against whatever crate the `#[derive(Readable)]` invocation lives
+//!     in, so this only works for structs defined inside the `term` crate
+//!     itself.
+//!   - `#[term(count = "other_field")]` - read a length-prefixed `Vec<T>` whose
+//!     length was already read into `other_field`.
+//!   - `#[term(tag = 104)]` - assert the next byte (via `peek`) equals the
+//!     given tag before reading the field.
+//!   - `#[term(magnitude_sign = "other_field")]` - read a `SMALL_BIG_EXT`/
+//!     `LARGE_BIG_EXT`-style body (a sign byte followed by `other_field`
+//!     magnitude bytes) into a `(Vec<u8>, u8)` of `(magnitude, sign)`,
+//!     matching `Term::BigInt`'s field order. `other_field` must already be
+//!     in scope, i.e. declared earlier in the struct.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Lit, Meta, NestedMeta};
+
+#[proc_macro_derive(Readable, attributes(term))]
+pub fn derive_readable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(named) => &named.named,
+            _ => panic!("#[derive(Readable)] only supports structs with named fields"),
+        },
+        _ => panic!("#[derive(Readable)] only supports structs"),
+    };
+
+    let mut reads = Vec::new();
+    let mut field_names = Vec::new();
+
+    for field in fields {
+        let field_name = field.ident.as_ref().unwrap();
+        let field_ty = &field.ty;
+        let opts = FieldOpts::parse(&field.attrs);
+
+        field_names.push(field_name.clone());
+
+        let read_expr = if let Some(len_field) = &opts.magnitude_sign {
+            quote! {
+                {
+                    let sign = reader.read_u8()?;
+                    let magnitude = reader.read(#len_field as usize)?.to_vec();
+                    (magnitude, sign)
+                }
+            }
+        } else if let Some(count_field) = &opts.count_field {
+            quote! {
+                {
+                    let mut elements = Vec::with_capacity(#count_field as usize);
+                    for _ in 0..#count_field {
+                        elements.push(reader.read_with()?);
+                    }
+                    elements
+                }
+            }
+        } else if opts.little_endian {
+            quote! { crate::reader::LittleEndian::swap_bytes_value(reader.read_with::<#field_ty>()?) }
+        } else {
+            quote! { reader.read_with::<#field_ty>()? }
+        };
+
+        let with_tag_check = if let Some(tag) = opts.tag {
+            quote! {
+                {
+                    let actual = reader.peek()?;
+                    if actual != #tag {
+                        return Err(crate::reader::ReadError::UnexpectedTag { expected: #tag, actual });
+                    }
+                    reader.read_u8()?;
+                    #read_expr
+                }
+            }
+        } else {
+            read_expr
+        };
+
+        reads.push(quote! {
+            let #field_name = #with_tag_check;
+        });
+    }
+
+    let expanded = quote! {
+        impl crate::reader::Readable for #name {
+            fn do_read(reader: &mut crate::reader::Reader) -> Result<Self, crate::reader::ReadError> {
+                #(#reads)*
+                Ok(#name { #(#field_names),* })
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+struct FieldOpts {
+    little_endian: bool,
+    count_field: Option<syn::Ident>,
+    tag: Option<u8>,
+    magnitude_sign: Option<syn::Ident>,
+}
+
+impl FieldOpts {
+    fn parse(attrs: &[syn::Attribute]) -> Self {
+        let mut opts = FieldOpts {
+            little_endian: false,
+            count_field: None,
+            tag: None,
+            magnitude_sign: None,
+        };
+
+        for attr in attrs {
+            if !attr.path.is_ident("term") {
+                continue;
+            }
+            let meta = match attr.parse_meta() {
+                Ok(Meta::List(list)) => list,
+                _ => continue,
+            };
+            for nested in meta.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("le") => {
+                        opts.little_endian = true;
+                    }
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("be") => {
+                        opts.little_endian = false;
+                    }
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("magnitude_sign") => {
+                        panic!(
+                            "#[term(magnitude_sign)] requires the name of the field holding the \
+                             magnitude length, e.g. #[term(magnitude_sign = \"n\")]"
+                        );
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("count") => {
+                        if let Lit::Str(s) = &nv.lit {
+                            opts.count_field = Some(syn::Ident::new(
+                                &s.value(),
+                                proc_macro2::Span::call_site(),
+                            ));
+                        }
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("magnitude_sign") => {
+                        if let Lit::Str(s) = &nv.lit {
+                            opts.magnitude_sign = Some(syn::Ident::new(
+                                &s.value(),
+                                proc_macro2::Span::call_site(),
+                            ));
+                        }
+                    }
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("tag") => {
+                        if let Lit::Int(i) = &nv.lit {
+                            opts.tag = Some(i.base10_parse::<u8>().unwrap());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        opts
+    }
+}